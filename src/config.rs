@@ -1,5 +1,9 @@
+use std::collections::HashMap;
+use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 
+use crate::node_handler::NodeHandler;
+
 #[derive(Debug, Deserialize)]
 pub struct ConvertRequest {
     pub url: String,
@@ -16,6 +20,29 @@ pub struct CrawlRequest {
     pub config: ConvertConfig,
     #[serde(default)]
     pub follow_relative: bool,
+    /// Max retries per URL, after the first attempt, for transient failures (timeouts, connection
+    /// errors, 429/5xx). Defaults to 4 when unset.
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    /// Base delay in milliseconds for the exponential backoff between retries (doubled each
+    /// attempt, with jitter added). Defaults to 1000ms when unset.
+    #[serde(default)]
+    pub retry_base_delay_ms: Option<u32>,
+    /// Return a `CrawlReport` (per-URL status plus aggregate counters) instead of a bare array
+    /// of results.
+    #[serde(default)]
+    pub report: bool,
+    /// Stream one `CrawlPageEvent` per line as newline-delimited JSON as each page finishes,
+    /// instead of buffering the whole crawl and returning it as one response. Also triggered by
+    /// an `Accept: application/x-ndjson` request header.
+    #[serde(default)]
+    pub stream: bool,
+    /// Look up (and populate) each page's fetched HTML in the Cache API before fetching over the
+    /// network, keyed on the page's own URL and respecting `config.cache_ttl_seconds`. Off by
+    /// default, since most crawls are one-shot and the extra Cache API round-trip per page isn't
+    /// worth it unless the same URLs are likely to recur across crawls.
+    #[serde(default)]
+    pub use_cache: bool,
 }
 
 #[derive(Debug, Deserialize, Default, Clone)]
@@ -30,6 +57,204 @@ pub struct ConvertConfig {
     pub include_metadata: bool,
     #[serde(default)]
     pub max_heading_level: u8,
+    /// Render the page in a headless browser before conversion instead of a plain HTTP GET.
+    #[serde(default)]
+    pub render: bool,
+    /// When `render` is set, wait for this selector to appear before reading `page.content()`.
+    #[serde(default)]
+    pub wait_for_selector: Option<String>,
+    /// When `render` is set, wait this many milliseconds after navigation before reading content.
+    #[serde(default)]
+    pub wait_ms: Option<u32>,
+    /// Output format for the response. Non-`Markdown` formats render the page in a browser
+    /// (implying `render`) and return the binary capture instead of converted text.
+    #[serde(default)]
+    pub output: OutputFormat,
+    /// Capture the full scrollable page rather than just the viewport (`Png`/`Pdf` only).
+    #[serde(default)]
+    pub full_page: bool,
+    /// Clip the capture to this CSS selector's bounding box (`Png` only).
+    #[serde(default)]
+    pub clip_selector: Option<String>,
+    /// Extra headers to send with the outbound fetch, merged over the generated fingerprint
+    /// headers (these take precedence on conflict).
+    #[serde(default)]
+    pub custom_headers: HashMap<String, String>,
+    /// Value for the `Referer` header on the outbound fetch.
+    #[serde(default)]
+    pub referer: Option<String>,
+    /// Maximum response body size in bytes for the outbound fetch; the fetch is aborted once the
+    /// accumulated body exceeds this. Defaults to 8MB when unset.
+    #[serde(default)]
+    pub max_body_bytes: Option<u32>,
+    /// Maximum number of redirects to follow before giving up. Defaults to 10 when unset.
+    #[serde(default)]
+    pub max_redirects: Option<u32>,
+    /// How `<a href>` links are rendered: inline `[text](href)`, or a numbered reference with the
+    /// href collected into a `[n]: href` list at the end of the document.
+    #[serde(default)]
+    pub link_style: LinkStyle,
+    /// Serialization used for the metadata block prepended to the output when `include_metadata`
+    /// is set.
+    #[serde(default)]
+    pub front_matter: FrontMatterFormat,
+    /// Layout used for `<dl>`/`<dt>`/`<dd>` definition lists.
+    #[serde(default)]
+    pub definition_list_style: DefinitionListStyle,
+    /// `Cache-Control: public, max-age=<N>` sent (and used as the Cache API TTL) for a successful
+    /// conversion. Defaults to 300 when unset.
+    #[serde(default)]
+    pub cache_ttl_seconds: Option<u32>,
+    /// Prepend a table of contents built from the document's headings. Each heading also gets an
+    /// explicit `{#slug}` anchor attribute so the TOC links resolve on renderers that don't
+    /// auto-slug headings the way GitHub does.
+    #[serde(default)]
+    pub table_of_contents: bool,
+    /// When no `<meta name="description">`/`og:description` is found, derive one from the
+    /// document's first `<p>`, truncated to this many characters at a word boundary. Defaults to
+    /// 200 when unset.
+    #[serde(default)]
+    pub auto_description_max_len: Option<u32>,
+    /// Overrides rendering for specific element kinds (links, images, code blocks, tables, ...);
+    /// unset elements fall back to the built-in rendering. Not settable from the JSON request
+    /// body — this is a hook for embedding this crate as a library, not a wire-format option.
+    #[serde(skip)]
+    pub node_handler: Option<Arc<dyn NodeHandler>>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Markdown,
+    Png,
+    Pdf,
+}
+
+impl ConvertConfig {
+    /// The fixed defaults the GET `/{url}` convenience route has always used, overridden
+    /// field-by-field by any matching query-string parameter (e.g. `?link_style=reference`).
+    /// Unrecognized keys are tolerated (ignored), but a recognized key with a value that fails to
+    /// parse is rejected with a message naming the offending key, so the caller can turn it into a
+    /// 400 instead of silently converting the page with a default it didn't ask for.
+    pub fn from_query_defaults(query: impl Iterator<Item = (String, String)>) -> Result<Self, String> {
+        let mut config = Self {
+            include_links: true,
+            clean_whitespace: true,
+            cleaning_rules: CleaningRules {
+                remove_scripts: true,
+                remove_styles: true,
+                remove_comments: true,
+                preserve_line_breaks: true,
+            },
+            preserve_headings: true,
+            include_metadata: true,
+            max_heading_level: 6,
+            ..Default::default()
+        };
+
+        for (key, value) in query {
+            config.apply_query_param(&key, &value)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Applies one query parameter, returning `Err` naming `key` when it's recognized but `value`
+    /// doesn't parse. An unrecognized `key` is tolerated and returns `Ok(())` unchanged.
+    fn apply_query_param(&mut self, key: &str, value: &str) -> Result<(), String> {
+        macro_rules! bool_field {
+            ($field:expr) => {
+                match parse_query_bool(value) {
+                    Some(v) => { $field = v; }
+                    None => return Err(format!("{} must be a boolean (true/false/1/0), got {:?}", key, value)),
+                }
+            };
+        }
+
+        match key {
+            "include_links" => bool_field!(self.include_links),
+            "clean_whitespace" => bool_field!(self.clean_whitespace),
+            "preserve_headings" => bool_field!(self.preserve_headings),
+            "include_metadata" => bool_field!(self.include_metadata),
+            "max_heading_level" => match value.parse() {
+                Ok(v) => self.max_heading_level = v,
+                Err(_) => return Err(format!("max_heading_level must be an integer, got {:?}", value)),
+            },
+            "remove_scripts" => bool_field!(self.cleaning_rules.remove_scripts),
+            "remove_styles" => bool_field!(self.cleaning_rules.remove_styles),
+            "remove_comments" => bool_field!(self.cleaning_rules.remove_comments),
+            "preserve_line_breaks" => bool_field!(self.cleaning_rules.preserve_line_breaks),
+            "link_style" => match value {
+                "inline" => self.link_style = LinkStyle::Inline,
+                "reference" => self.link_style = LinkStyle::Reference,
+                _ => return Err(format!("link_style must be one of inline/reference, got {:?}", value)),
+            },
+            "front_matter" => match value {
+                "plain" => self.front_matter = FrontMatterFormat::Plain,
+                "yaml" => self.front_matter = FrontMatterFormat::Yaml,
+                "toml" => self.front_matter = FrontMatterFormat::Toml,
+                _ => return Err(format!("front_matter must be one of plain/yaml/toml, got {:?}", value)),
+            },
+            "cache_ttl_seconds" => match value.parse() {
+                Ok(v) => self.cache_ttl_seconds = Some(v),
+                Err(_) => return Err(format!("cache_ttl_seconds must be an integer, got {:?}", value)),
+            },
+            "definition_list_style" => match value {
+                "term" => self.definition_list_style = DefinitionListStyle::Term,
+                "bullet" => self.definition_list_style = DefinitionListStyle::Bullet,
+                _ => return Err(format!("definition_list_style must be one of term/bullet, got {:?}", value)),
+            },
+            "table_of_contents" => bool_field!(self.table_of_contents),
+            "auto_description_max_len" => match value.parse() {
+                Ok(v) => self.auto_description_max_len = Some(v),
+                Err(_) => return Err(format!("auto_description_max_len must be an integer, got {:?}", value)),
+            },
+            "referer" => self.referer = Some(value.to_string()),
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_query_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStyle {
+    #[default]
+    Inline,
+    Reference,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DefinitionListStyle {
+    /// PHP-Markdown-Extra's `Term\n: definition` layout.
+    #[default]
+    Term,
+    /// `Term` on its own line, followed by its definition(s) as an indented bullet list, for
+    /// renderers that don't support the Markdown Extra definition-list syntax.
+    Bullet,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FrontMatterFormat {
+    /// The ad-hoc `# Title` heading plus a `---`-delimited `Key: value` header this crate emitted
+    /// before front matter was configurable. Kept as the default so existing callers see no change
+    /// in output unless they opt into `Yaml`/`Toml`.
+    #[default]
+    Plain,
+    Yaml,
+    Toml,
 }
 
 #[derive(Debug, Deserialize, Default, Clone)]
@@ -47,8 +272,92 @@ pub struct CrawlResult {
     pub depth: u32,
 }
 
-#[derive(Debug)]
+/// Final outcome recorded for a single URL encountered during a crawl, whether or not it was
+/// ever fetched. Mirrors how a link-checker accumulates a status per URL rather than only
+/// reporting successes.
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum UrlStatus {
+    Fetched,
+    SkippedByDomain,
+    SkippedByRobots,
+    /// This URL redirected to a destination some other queued URL had already claimed (or
+    /// redirected to), so it was not converted a second time.
+    SkippedDuplicate,
+    FetchError { message: String },
+    OverSize,
+    DepthLimited,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CrawlUrlReport {
+    pub url: String,
+    pub depth: u32,
+    #[serde(flatten)]
+    pub status: UrlStatus,
+    pub outbound_links: u32,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct CrawlSummary {
+    pub fetched: u32,
+    pub errors: u32,
+    pub skipped: u32,
+    pub max_depth_reached: u32,
+}
+
+/// Structured crawl output returned when `CrawlRequest::report` is set: the converted pages
+/// alongside a per-URL status trail and aggregate counters, so a caller can tell a successful
+/// empty crawl apart from one that hit errors on every page.
+#[derive(Debug, Serialize)]
+pub struct CrawlReport {
+    pub results: Vec<CrawlResult>,
+    pub urls: Vec<CrawlUrlReport>,
+    pub summary: CrawlSummary,
+}
+
+/// One page's outcome as it finishes during a crawl: the same `url`/`depth`/`status`/
+/// `outbound_links` shape as `CrawlUrlReport`, plus the converted Markdown when the page was
+/// actually fetched and kept within the crawl's result limit. Streaming mode serializes one of
+/// these per NDJSON line as each page completes; non-streaming mode accumulates them into a
+/// `CrawlReport`'s `results` and `urls` instead.
+#[derive(Debug, Serialize, Clone)]
+pub struct CrawlPageEvent {
+    pub url: String,
+    pub depth: u32,
+    #[serde(flatten)]
+    pub status: UrlStatus,
+    pub outbound_links: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub markdown: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
 pub struct HtmlConversionResult {
     pub markdown: String,
     pub links: Vec<String>,
+    pub metadata: ConvertMetadata,
+}
+
+/// The subset of `MetadataHandler`'s fields worth surfacing as structured data, rather than only
+/// as text folded into the Markdown body's front matter.
+#[derive(Debug, Serialize, Default)]
+pub struct ConvertMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub date: Option<String>,
+    pub description: Option<String>,
+    pub language: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// JSON body served by the GET/POST conversion routes when the client negotiates
+/// `application/json` via `Accept`: the page's URL and converted Markdown alongside the metadata
+/// `MetadataHandler` collected, instead of only folding it into the Markdown string's front
+/// matter where a JSON consumer can't get at it without re-parsing.
+#[derive(Debug, Serialize)]
+pub struct ConvertJsonResult {
+    pub url: String,
+    pub markdown: String,
+    pub metadata: ConvertMetadata,
 }
\ No newline at end of file
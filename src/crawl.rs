@@ -4,26 +4,92 @@ use std::sync::Arc;
 use futures::lock::Mutex;
 use futures::{StreamExt, SinkExt};
 use std::collections::HashSet;
+use std::time::Duration;
+use rand::Rng;
 use url::Url;
 use regex::Regex;
 use lazy_static::lazy_static;
 
-use crate::config::CrawlRequest;
-use crate::fetch::fetch_url_with_timeout;
+use crate::config::{
+    ConvertConfig, CrawlPageEvent, CrawlRequest, CrawlReport, CrawlResult, CrawlSummary,
+    CrawlUrlReport, UrlStatus,
+};
+use crate::fetch::FetchSession;
+use crate::fingerprint::seeded_rng;
 use crate::markdown::html_to_markdown;
+use crate::robots;
 
 lazy_static! {
     static ref URL_REGEX: Regex = Regex::new(r"^https?://").unwrap();
 }
 
-pub async fn handle_crawl(request: CrawlRequest) -> worker::Result<Vec<String>> {
-    let (mut url_tx, mut url_rx) = mpsc::unbounded::<(String, u32)>();
-    let (result_tx, mut result_rx) = mpsc::unbounded::<String>();
+pub async fn handle_crawl(request: CrawlRequest) -> worker::Result<CrawlReport> {
+    let (_, mut event_rx) = run_crawl(request).await?;
+
+    let mut results = Vec::new();
+    let mut urls = Vec::new();
+    while let Some(event) = event_rx.next().await {
+        if let (UrlStatus::Fetched, Some(markdown)) = (&event.status, &event.markdown) {
+            results.push(CrawlResult {
+                url: event.url.clone(),
+                markdown: markdown.clone(),
+                depth: event.depth,
+            });
+        }
+        urls.push(CrawlUrlReport {
+            url: event.url,
+            depth: event.depth,
+            status: event.status,
+            outbound_links: event.outbound_links,
+        });
+    }
+
+    console_log!("Collected {} results, {} url reports.", results.len(), urls.len());
+    let summary = summarize(&urls);
+
+    Ok(CrawlReport { results, urls, summary })
+}
+
+/// Newline-delimited JSON variant of [`handle_crawl`]: instead of buffering the whole crawl, each
+/// page's `CrawlPageEvent` is serialized onto its own line as soon as that page finishes, so a
+/// caller can begin processing early pages while later fetches are still in flight. Callers that
+/// don't ask for streaming use [`handle_crawl`] instead.
+pub async fn handle_crawl_stream(
+    request: CrawlRequest,
+) -> worker::Result<impl futures::Stream<Item = worker::Result<Vec<u8>>>> {
+    let (_, event_rx) = run_crawl(request).await?;
+    Ok(event_rx.map(|event| {
+        let mut line = serde_json::to_vec(&event)
+            .map_err(|e| Error::RustError(format!("Failed to serialize crawl event: {}", e)))?;
+        line.push(b'\n');
+        Ok(line)
+    }))
+}
+
+/// Starts the crawl's dispatcher in the background and returns the (sender kept alive for the
+/// caller's convenience, receiver of) `CrawlPageEvent`s it emits -- one per page, in the order
+/// each page finishes, regardless of whether the caller wants a single combined report or an
+/// NDJSON stream.
+async fn run_crawl(
+    request: CrawlRequest,
+) -> worker::Result<(mpsc::UnboundedSender<CrawlPageEvent>, mpsc::UnboundedReceiver<CrawlPageEvent>)> {
+    // Frontier is bounded so a burst of link discovery applies real backpressure instead of
+    // growing without limit; workers pull from it directly rather than being pre-partitioned.
+    let (mut frontier_tx, mut frontier_rx) = mpsc::channel::<(String, u32)>(256);
+    let (event_tx, event_rx) = mpsc::unbounded::<CrawlPageEvent>();
 
     let visited = Arc::new(Mutex::new(HashSet::new()));
     let results_counter = Arc::new(Mutex::new(0u32));
+    // One fingerprint + cookie jar for the whole crawl, so every page of the same crawl
+    // presents as one coherent browser identity instead of a fresh one per request.
+    let session = Arc::new(FetchSession::new());
+    let robots_cache = robots::new_cache();
+    let max_retries = request.max_retries.unwrap_or(4);
+    let retry_base_delay_ms = request.retry_base_delay_ms.unwrap_or(1000);
+    let use_cache = request.use_cache;
     let limit = request.limit;
     let concurrency_limit = 6;
+    let semaphore = Semaphore::new(concurrency_limit);
 
     let base_url_res = Url::parse(&request.url);
     let base_domain = match base_url_res {
@@ -37,65 +103,147 @@ pub async fn handle_crawl(request: CrawlRequest) -> worker::Result<Vec<String>>
         let mut visited_set = visited.lock().await;
         if !visited_set.insert(request.url.clone()) {
             console_warn!("Initial URL {} already visited?", request.url);
-            return Ok(Vec::new());
+            return Ok((event_tx, event_rx));
         }
-        url_tx.send((request.url.clone(), 0)).await
+        frontier_tx.send((request.url.clone(), 0)).await
             .map_err(|e| Error::RustError(format!("Failed to send initial URL: {}", e)))?;
     }
 
-    console_log!("Initial URL sent to channel.");
-
+    console_log!("Initial URL sent to frontier.");
     console_log!(
-        "Starting {} workers, limit: {}, depth: {}",
+        "Starting dispatcher with concurrency {}, limit: {}, depth: {}",
         concurrency_limit,
         request.limit,
         request.max_depth
     );
 
-    let mut worker_txs = Vec::with_capacity(concurrency_limit as usize);
-    for worker_id in 0..concurrency_limit {
-        let (worker_tx, mut worker_rx) = mpsc::unbounded::<(String, u32)>();
-        worker_txs.push(worker_tx);
-
-        let result_tx = result_tx.clone();
-        let visited = Arc::clone(&visited);
-        let results_counter = Arc::clone(&results_counter);
-        let config = request.config.clone();
-        let max_depth = request.max_depth;
-        let follow_relative = request.follow_relative;
-        let base_domain = base_domain.clone();
-        let mut url_tx = url_tx.clone();
-        let limit = limit;
-
-        wasm_bindgen_futures::spawn_local(async move {
-            while let Some((url, depth)) = worker_rx.next().await {
+    let dispatch_event_tx = event_tx.clone();
+    let config_template = request.config.clone();
+    let max_depth = request.max_depth;
+    let follow_relative = request.follow_relative;
+
+    wasm_bindgen_futures::spawn_local(async move {
+        let event_tx = dispatch_event_tx;
+        let mut task_id = 0u64;
+
+        while let Some((url, depth)) = frontier_rx.next().await {
+            let permit = semaphore.acquire().await;
+            task_id += 1;
+
+            let event_tx = event_tx.clone();
+            let visited = Arc::clone(&visited);
+            let results_counter = Arc::clone(&results_counter);
+            let session = Arc::clone(&session);
+            let robots_cache = Arc::clone(&robots_cache);
+            let config = config_template.clone();
+            let max_retries = max_retries;
+            let retry_base_delay_ms = retry_base_delay_ms;
+            let use_cache = use_cache;
+            let max_depth = max_depth;
+            let follow_relative = follow_relative;
+            let base_domain = base_domain.clone();
+            let mut frontier_tx = frontier_tx.clone();
+            let limit = limit;
+
+            wasm_bindgen_futures::spawn_local(async move {
+                let _permit = permit;
+
                 console_log!(
-                    "W{}: Processing: {} (Depth {})",
-                    worker_id,
+                    "T{}: Processing: {} (Depth {})",
+                    task_id,
                     url.chars().take(60).collect::<String>(),
                     depth
                 );
 
-                let markdown = match fetch_url_with_timeout(&url, 10000).await {
-                    Ok(html) => html_to_markdown(&html, config.clone()),
+                let robots_decision = robots::check(&robots_cache, &url).await;
+                if !robots_decision.allowed {
+                    console_log!(
+                        "T{}: Skipping {} (disallowed by robots.txt)",
+                        task_id,
+                        url.chars().take(60).collect::<String>()
+                    );
+                    send_event(&event_tx, task_id, CrawlPageEvent {
+                        url,
+                        depth,
+                        status: UrlStatus::SkippedByRobots,
+                        outbound_links: 0,
+                        markdown: None,
+                    });
+                    return;
+                }
+                if let Some(crawl_delay) = robots_decision.crawl_delay {
+                    worker::Delay::from(crawl_delay).await;
+                }
+
+                let (html, final_url) = match fetch_page(
+                    &session,
+                    &url,
+                    &config,
+                    max_retries,
+                    retry_base_delay_ms,
+                    use_cache,
+                )
+                .await
+                {
+                    Ok(result) => result,
                     Err(e) => {
                         console_error!(
-                            "W{}: Error fetching/processing {}: {}",
-                            worker_id,
+                            "T{}: Error fetching/processing {}: {}",
+                            task_id,
                             url.chars().take(60).collect::<String>(),
                             e
                         );
-                        continue;
+                        let message = e.to_string();
+                        let status = if message.contains("exceeded max size") {
+                            UrlStatus::OverSize
+                        } else {
+                            UrlStatus::FetchError { message }
+                        };
+                        send_event(&event_tx, task_id, CrawlPageEvent {
+                            url,
+                            depth,
+                            status,
+                            outbound_links: 0,
+                            markdown: None,
+                        });
+                        return;
                     }
                 };
 
+                if final_url != url {
+                    // A different entry URL that redirects to the same destination should be
+                    // recognized as already-visited rather than crawled and converted again: if
+                    // another task already claimed `final_url` (either as its own entry point or
+                    // via its own redirect), bail out here instead of converting and emitting a
+                    // second `Fetched` event for a page that's already been processed.
+                    let mut visited_set = visited.lock().await;
+                    if !visited_set.insert(final_url.clone()) {
+                        console_log!(
+                            "T{}: Skipping {} (redirects to already-visited {})",
+                            task_id,
+                            url.chars().take(60).collect::<String>(),
+                            final_url.chars().take(60).collect::<String>()
+                        );
+                        send_event(&event_tx, task_id, CrawlPageEvent {
+                            url,
+                            depth,
+                            status: UrlStatus::SkippedDuplicate,
+                            outbound_links: 0,
+                            markdown: None,
+                        });
+                        return;
+                    }
+                }
+
+                let markdown = html_to_markdown(&html, config.clone());
+
                 let should_send = {
                     let mut count = results_counter.lock().await;
                     if *count < limit {
                         *count += 1;
                         console_log!(
-                            "W{}: Incremented result count to {}/{} for {}",
-                            worker_id,
+                            "T{}: Incremented result count to {}/{} for {}",
+                            task_id,
                             *count,
                             limit,
                             url.chars().take(60).collect::<String>()
@@ -106,102 +254,286 @@ pub async fn handle_crawl(request: CrawlRequest) -> worker::Result<Vec<String>>
                     }
                 };
 
-                if should_send {
-                    if let Err(e) = result_tx.unbounded_send(markdown.markdown.clone()) {
-                        console_error!("W{}: Error sending result for {}: {}", worker_id, url, e);
-                    }
-                }
+                send_event(&event_tx, task_id, CrawlPageEvent {
+                    url: url.clone(),
+                    depth,
+                    status: UrlStatus::Fetched,
+                    outbound_links: markdown.links.len() as u32,
+                    markdown: should_send.then(|| markdown.markdown.clone()),
+                });
+
+                // Resolve relative links against the post-redirect URL, not the entry URL that was
+                // queued: if the fetch redirected somewhere with a different path, the entry URL
+                // is the wrong base and would join relative links to a broken destination.
+                let base_url_for_join = Url::parse(&final_url).ok();
+                let links_within_depth = depth < max_depth && *results_counter.lock().await < limit;
+
+                for link in &markdown.links {
+                    let is_absolute = URL_REGEX.is_match(link);
+                    let absolute_url = if is_absolute {
+                        Some(link.clone())
+                    } else if follow_relative {
+                        base_url_for_join
+                            .as_ref()
+                            .and_then(|base| base.join(link).ok())
+                            .map(|u| u.to_string())
+                    } else {
+                        None
+                    };
 
-                if depth < max_depth {
-                    let count = *results_counter.lock().await;
-                    if count < limit {
-                        let base_url_for_join = Url::parse(&url).ok();
-                        for link in markdown.links {
-                            let is_absolute = URL_REGEX.is_match(&link);
-                            let absolute_url = if is_absolute {
-                                Some(link)
-                            } else if follow_relative {
-                                base_url_for_join
-                                    .as_ref()
-                                    .and_then(|base| base.join(&link).ok())
-                                    .map(|u| u.to_string())
-                            } else {
-                                None
-                            };
-
-                            if let Some(absolute_url) = absolute_url {
-                                if absolute_url.len() > 512 {
+                    if let Some(absolute_url) = absolute_url {
+                        if absolute_url.len() > 512 {
+                            continue;
+                        }
+                        if let Ok(abs_parsed) = Url::parse(&absolute_url) {
+                            if abs_parsed.scheme() != "http" && abs_parsed.scheme() != "https" {
+                                continue;
+                            }
+
+                            if !links_within_depth {
+                                // The page itself was fetched, but discovery stops here: either
+                                // the depth cap or the result limit was already reached, so this
+                                // link is recorded as reachable-but-not-pursued rather than
+                                // silently dropped.
+                                send_event(&event_tx, task_id, CrawlPageEvent {
+                                    url: absolute_url,
+                                    depth: depth + 1,
+                                    status: UrlStatus::DepthLimited,
+                                    outbound_links: 0,
+                                    markdown: None,
+                                });
+                                continue;
+                            }
+
+                            if let Some(ref domain_filter) = base_domain {
+                                if abs_parsed.domain() != Some(domain_filter.as_str()) {
+                                    send_event(&event_tx, task_id, CrawlPageEvent {
+                                        url: absolute_url,
+                                        depth: depth + 1,
+                                        status: UrlStatus::SkippedByDomain,
+                                        outbound_links: 0,
+                                        markdown: None,
+                                    });
                                     continue;
                                 }
-                                if let Ok(abs_parsed) = Url::parse(&absolute_url) {
-                                    if let Some(ref domain_filter) = base_domain {
-                                        if abs_parsed.domain() != Some(domain_filter.as_str()) {
-                                            continue;
-                                        }
-                                    }
-                                    if abs_parsed.scheme() != "http" && abs_parsed.scheme() != "https" {
-                                        continue;
-                                    }
-
-                                    let mut visited_set = visited.lock().await;
-                                    if visited_set.insert(absolute_url.clone()) {
-                                        console_log!(
-                                            "W{}: Queuing: {} (Depth {})",
-                                            worker_id,
-                                            absolute_url.chars().take(60).collect::<String>(),
-                                            depth + 1
-                                        );
-                                        if let Err(e) = url_tx.send((absolute_url.clone(), depth + 1)).await {
-                                            console_error!(
-                                                "W{}: Error sending URL {}: {}",
-                                                worker_id,
-                                                absolute_url.chars().take(60).collect::<String>(),
-                                                e
-                                            );
-                                            visited_set.remove(&absolute_url);
-                                        }
-                                    }
+                            }
+
+                            let mut visited_set = visited.lock().await;
+                            if visited_set.insert(absolute_url.clone()) {
+                                console_log!(
+                                    "T{}: Queuing: {} (Depth {})",
+                                    task_id,
+                                    absolute_url.chars().take(60).collect::<String>(),
+                                    depth + 1
+                                );
+                                if let Err(e) =
+                                    frontier_tx.send((absolute_url.clone(), depth + 1)).await
+                                {
+                                    console_error!(
+                                        "T{}: Error sending URL {}: {}",
+                                        task_id,
+                                        absolute_url.chars().take(60).collect::<String>(),
+                                        e
+                                    );
+                                    visited_set.remove(&absolute_url);
                                 }
                             }
                         }
                     }
                 }
+            });
+        }
+    });
+
+    console_log!("Dispatcher spawned; events will arrive as pages complete.");
+
+    Ok((event_tx, event_rx))
+}
+
+/// Sends `event` to the crawl's event channel, logging (rather than panicking) if every receiver
+/// has already gone away.
+fn send_event(event_tx: &mpsc::UnboundedSender<CrawlPageEvent>, task_id: u64, event: CrawlPageEvent) {
+    let url = event.url.clone();
+    if let Err(e) = event_tx.unbounded_send(event) {
+        console_error!("T{}: Error sending crawl event for {}: {}", task_id, url, e);
+    }
+}
+
+/// Aggregates a per-URL status trail into the counters a caller would otherwise have to derive
+/// by scanning `urls` themselves: how many pages were fetched, how many failed, how many were
+/// skipped outright, and how deep the crawl actually went.
+fn summarize(urls: &[CrawlUrlReport]) -> CrawlSummary {
+    let mut summary = CrawlSummary::default();
+    for entry in urls {
+        match entry.status {
+            UrlStatus::Fetched => {
+                summary.fetched += 1;
+                summary.max_depth_reached = summary.max_depth_reached.max(entry.depth);
             }
-            console_log!("W{}: Finished processing.", worker_id);
-        });
+            UrlStatus::FetchError { .. } | UrlStatus::OverSize => summary.errors += 1,
+            UrlStatus::SkippedByDomain | UrlStatus::SkippedByRobots | UrlStatus::SkippedDuplicate => summary.skipped += 1,
+            UrlStatus::DepthLimited => {}
+        }
     }
+    summary
+}
 
-    wasm_bindgen_futures::spawn_local(async move {
-        let mut next_worker = 0;
+/// A counting semaphore built from a pre-filled bounded channel: `permits` unit tokens are
+/// seeded into the channel up front, `acquire` pops one (blocking while none are available), and
+/// dropping the returned permit pushes it back. Guarding the receiver with `Mutex` lets many
+/// tasks share one `Semaphore` the same way the crawl's other shared state is shared.
+struct Semaphore {
+    tx: mpsc::Sender<()>,
+    rx: Mutex<mpsc::Receiver<()>>,
+}
 
-        while let Some((url, depth)) = url_rx.next().await {
-            if let Some(worker_tx) = worker_txs.get(next_worker) {
-                if let Err(e) = worker_tx.unbounded_send((url.clone(), depth)) {
-                    console_error!("Error sending URL to worker {}: {}", next_worker, e);
-                }
-            }
+impl Semaphore {
+    fn new(permits: usize) -> Arc<Self> {
+        let (mut tx, rx) = mpsc::channel(permits);
+        for _ in 0..permits {
+            tx.try_send(()).expect("channel sized to permit count");
+        }
+        Arc::new(Self { tx, rx: Mutex::new(rx) })
+    }
+
+    async fn acquire(self: &Arc<Self>) -> SemaphorePermit {
+        self.rx.lock().await.next().await;
+        SemaphorePermit { tx: self.tx.clone() }
+    }
+}
 
-            next_worker = (next_worker + 1) % worker_txs.len();
+struct SemaphorePermit {
+    tx: mpsc::Sender<()>,
+}
+
+impl Drop for SemaphorePermit {
+    fn drop(&mut self) {
+        let _ = self.tx.try_send(());
+    }
+}
+
+/// Fetches `url`, optionally checking (and populating) the Cache API first when `use_cache` is
+/// set -- keyed on the page's own URL and holding the raw fetched HTML, so a crawl that revisits a
+/// URL an earlier crawl cached can skip the network fetch entirely while the entry is still fresh.
+/// These entries are separate from the GET `/{url}` route's: that route keys on the Worker's own
+/// request URL and stores converted output, so a crawl never hits an entry written by a single
+/// conversion, and a single conversion never hits one written by a crawl. A cache hit can't tell us the
+/// post-redirect URL, so `url` itself is returned alongside it in that case.
+///
+/// Never caches when `custom_headers`/`referer` are set: those exist so a caller can pass
+/// `Authorization` tokens or other per-caller/per-crawl auth, and the Cache API key here doesn't
+/// account for them -- caching that response would hand one caller's authenticated fetch to any
+/// other caller who later crawls the same URL.
+async fn fetch_page(
+    session: &FetchSession,
+    url: &str,
+    config: &ConvertConfig,
+    max_retries: u32,
+    retry_base_delay_ms: u32,
+    use_cache: bool,
+) -> worker::Result<(String, String)> {
+    let use_cache = use_cache && config.custom_headers.is_empty() && config.referer.is_none();
+    let cache_key = if use_cache { Request::new(url, Method::Get).ok() } else { None };
+
+    if let Some(key) = &cache_key {
+        if let Ok(Some(mut cached)) = Cache::default().get(key, true).await {
+            if let Ok(text) = cached.text().await {
+                return Ok((text, url.to_string()));
+            }
         }
-    });
+    }
 
-    drop(result_tx);
-    console_log!("Original result sender dropped.");
+    let (html, final_url) = fetch_with_retries(session, url, config, max_retries, retry_base_delay_ms).await?;
 
-    let mut results = Vec::with_capacity(request.limit as usize);
-    while results.len() < request.limit as usize {
-        match result_rx.next().await {
-            Some(markdown) => {
-                results.push(markdown);
-                console_log!("Collected result {}/{}", results.len(), request.limit);
+    if let Some(key) = cache_key {
+        let ttl = config.cache_ttl_seconds.unwrap_or(300);
+        if let Ok(mut resp) = Response::ok(html.clone()) {
+            if resp.headers_mut().set("Cache-Control", &format!("public, max-age={}", ttl)).is_ok() {
+                let _ = Cache::default().put(key, resp).await;
             }
-            None => {
-                console_log!("Result channel closed.");
-                break;
+        }
+    }
+
+    Ok((html, final_url))
+}
+
+/// Fetches `url` through `session`, retrying transient failures (timeouts, connection errors,
+/// 429/5xx) up to `max_retries` times with exponential backoff from `base_delay_ms`, plus jitter
+/// to avoid every retrying worker hammering the same origin in lockstep. Non-transient errors
+/// (e.g. a 404) are returned immediately without retrying. Returns the body alongside the final
+/// (post-redirect) URL.
+async fn fetch_with_retries(
+    session: &FetchSession,
+    url: &str,
+    config: &ConvertConfig,
+    max_retries: u32,
+    base_delay_ms: u32,
+) -> worker::Result<(String, String)> {
+    let mut retries = 0u32;
+    loop {
+        match session
+            .fetch_with_options(
+                url,
+                10000,
+                &config.custom_headers,
+                config.referer.as_deref(),
+                config.max_body_bytes.map(|v| v as usize),
+                config.max_redirects,
+            )
+            .await
+        {
+            Ok(result) => return Ok(result),
+            Err(e) => {
+                if retries >= max_retries || !is_transient_error(&e) {
+                    return Err(e);
+                }
+
+                let backoff_ms = base_delay_ms.saturating_mul(1u32 << retries.min(16)).min(16_000);
+                let jitter_ms = seeded_rng().gen_range(0..=(backoff_ms / 4).max(1));
+                let delay_ms = backoff_ms.saturating_add(jitter_ms);
+                retries += 1;
+
+                console_warn!(
+                    "Retrying {} after transient error (attempt {}/{}): {}",
+                    url.chars().take(60).collect::<String>(),
+                    retries,
+                    max_retries,
+                    e
+                );
+                worker::Delay::from(Duration::from_millis(delay_ms as u64)).await;
             }
         }
     }
+}
 
-    console_log!("Collected {} results (limit was {}).", results.len(), request.limit);
-    Ok(results)
+/// Whether `e` represents a failure worth retrying: network/timeout errors (no HTTP status at
+/// all), 429, or any 5xx. Other HTTP statuses (404, 401, ...) are permanent and returned as-is, as
+/// are the errors the fetch layer raises about a response it did get (invalid custom header,
+/// non-HTML content type, body read/size failure, redirect problems).
+fn is_transient_error(e: &Error) -> bool {
+    let msg = e.to_string();
+    const PERMANENT_MARKERS: [&str; 7] = [
+        "Invalid custom header",
+        "Skipping non-HTML content-type",
+        "Body read failed",
+        "redirects starting from",
+        "Redirect loop detected",
+        "Redirect status",
+        "Invalid redirect Location",
+    ];
+    if PERMANENT_MARKERS.iter().any(|marker| msg.contains(marker)) {
+        return false;
+    }
+    match msg.find("HTTP error ") {
+        Some(idx) => {
+            let code: u32 = msg[idx + "HTTP error ".len()..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect::<String>()
+                .parse()
+                .unwrap_or(0);
+            code == 429 || (500..600).contains(&code)
+        }
+        None => true,
+    }
 }
\ No newline at end of file
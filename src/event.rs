@@ -0,0 +1,67 @@
+/// A GFM column alignment, carried on `Container::TableCell` so the renderer can pick the right
+/// delimiter-row marker (`:---`, `:---:`, `---:`) for the column the cell belongs to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListKind {
+    Unordered,
+    Ordered(u32),
+}
+
+/// A semantic block or inline span the parser has entered or left. A renderer matches on these
+/// instead of on HTML tag names, the way jotdown's `Parser` yields `Container`s rather than raw
+/// markup.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Container {
+    Paragraph,
+    Heading(u8),
+    BlockQuote,
+    CodeBlock { lang: Option<String> },
+    List(ListKind),
+    ListItem { checked: Option<bool> },
+    Table,
+    TableRow,
+    TableCell { alignment: Option<Alignment> },
+    Emphasis,
+    Strong,
+    Strikethrough,
+    Mark,
+    Underline,
+    InlineCode,
+    Link { href: String },
+    DefinitionList,
+    DefinitionTerm,
+    DefinitionDescription,
+    /// A block-level element (`<div>`, `<article>`, `<section>`, or a table element seen outside
+    /// a table) that doesn't carry Markdown semantics of its own, but still separates its
+    /// contents from surrounding text with blank lines.
+    Generic,
+}
+
+/// A leaf inline element with no content of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    Image { src: String, alt: String },
+}
+
+/// One step of the document, in document order. `Start`/`End` bracket a `Container`'s content;
+/// `Str` is literal text; `Atom` is a leaf with no children. A `Parser` walks the DOM and yields
+/// these; a `Renderer` (or any `.map`/`.filter` stage placed in between) turns them into
+/// Markdown.
+///
+/// Events are collected into an owned `Vec` rather than borrowing from the DOM, so `Str` holds an
+/// owned `String` rather than a `Cow` — the cleaned/collapsed text a `Parser` produces rarely
+/// matches the original tendril byte-for-byte anyway, so there's little zero-copy opportunity to
+/// preserve here.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Start(Container),
+    End(Container),
+    Str(String),
+    Atom(Atom),
+}
@@ -1,72 +1,401 @@
 use worker::*;
-use std::time::Duration;
-use crate::fingerprint::FingerprintCache;
+use std::collections::{HashMap, HashSet};
+use futures::lock::Mutex;
+use futures::StreamExt;
+use url::Url;
+use crate::fingerprint::{BrowserFingerprint, FingerprintCache};
+use crate::utils::split_set_cookie_string;
 
-pub async fn fetch_url_with_timeout(url: &str, _timeout_ms: u32) -> worker::Result<String> {
+/// Default cap on a fetched response body, used when a caller doesn't set
+/// `ConvertConfig::max_body_bytes`. Large enough for ordinary pages, small enough that one
+/// pathological URL can't exhaust the Worker's memory.
+const DEFAULT_MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+
+/// Default cap on the number of redirects `fetch_with_fingerprint` will follow, used when a
+/// caller doesn't set `ConvertConfig::max_redirects`.
+const DEFAULT_MAX_REDIRECTS: u32 = 10;
+
+/// Whether `name`/`value` are a valid HTTP header: `name` a non-empty run of visible ASCII with no
+/// separator characters (the `token` grammar from RFC 9110 section 5.1, trimmed to what's actually
+/// worth rejecting here), `value` free of control characters that would otherwise either be
+/// silently stripped or break the request line. `ConvertConfig::custom_headers` is caller-supplied,
+/// so a caller can hand this a name/value that isn't a well-formed header at all -- that should
+/// fail the request with a 400, not surface as a generic 500 from the underlying `Headers::set`.
+fn is_valid_header(name: &str, value: &str) -> bool {
+    let valid_name = !name.is_empty()
+        && name.is_ascii()
+        && name.chars().all(|c| {
+            c.is_ascii_graphic() && !matches!(c, '(' | ')' | '<' | '>' | '@' | ',' | ';' | ':' | '\\' | '"' | '/' | '[' | ']' | '?' | '=' | '{' | '}')
+        });
+    let valid_value = value.chars().all(|c| c == '\t' || (!c.is_control() && c != '\u{7f}'));
+    valid_name && valid_value
+}
+
+/// Whether `content_type` (the raw `Content-Type` header value, parameters and all) looks like
+/// something `html_to_markdown` can make sense of. A missing header is treated as convertible
+/// since plenty of servers omit it for plain HTML.
+fn is_convertible_content_type(content_type: &str) -> bool {
+    let base = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    base.is_empty() || base.starts_with("text/") || base.contains("html") || base.contains("xml")
+}
+
+/// Reads `response`'s body as a stream, aborting as soon as the accumulated size exceeds
+/// `max_bytes` instead of buffering the whole thing first.
+async fn read_body_capped(response: Response, max_bytes: usize) -> worker::Result<String> {
+    let mut stream = response.stream()?;
+    let mut buf: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = stream.next().await {
+        buf.extend_from_slice(&chunk?);
+        if buf.len() > max_bytes {
+            return Err(Error::RustError(format!(
+                "Response body exceeded max size of {} bytes",
+                max_bytes
+            )));
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+// Service binding name for the Browser Rendering worker (Cloudflare's Puppeteer-over-CDP
+// binding). Configured in wrangler.toml as a `browser` binding pointed at this Worker.
+const BROWSER_BINDING: &str = "BROWSER";
+
+/// Calls the Browser Rendering binding with the given action and returns its raw response.
+/// `action` is one of "content", "screenshot", "pdf".
+async fn render_via_browser(
+    env: &Env,
+    action: &str,
+    url: &str,
+    wait_for_selector: Option<&str>,
+    wait_ms: Option<u32>,
+    full_page: bool,
+    clip_selector: Option<&str>,
+) -> worker::Result<Response> {
+    let fetcher: Fetcher = env.service(BROWSER_BINDING)?;
+
+    let payload = serde_json::json!({
+        "action": action,
+        "url": url,
+        "waitForSelector": wait_for_selector,
+        "waitMs": wait_ms,
+        "fullPage": full_page,
+        "clipSelector": clip_selector,
+    })
+    .to_string();
+
+    let mut headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+
+    let mut opts = RequestInit::new();
+    opts.method = Method::Post;
+    opts.headers = headers;
+    opts.body = Some(wasm_bindgen::JsValue::from_str(&payload));
+
+    let request = Request::new_with_init("https://browser-render/render", &opts)?;
+
+    console_log!("Rendering URL via browser binding ({}): {}", action, url);
+
+    let response = fetcher.fetch_request(request).await?;
+
+    if response.status_code() >= 400 {
+        return Err(Error::RustError(format!(
+            "Browser rendering binding returned status {} for {}",
+            response.status_code(),
+            url
+        )));
+    }
+
+    Ok(response)
+}
+
+/// Renders `url` in a headless browser via Cloudflare's Browser Rendering binding and returns
+/// the fully-rendered `page.content()` HTML. Callers should fall back to
+/// `fetch_url_with_timeout` when this returns an error, e.g. because the binding isn't
+/// configured in this environment.
+pub async fn fetch_rendered_html(
+    env: &Env,
+    url: &str,
+    wait_for_selector: Option<&str>,
+    wait_ms: Option<u32>,
+) -> worker::Result<String> {
+    let mut response =
+        render_via_browser(env, "content", url, wait_for_selector, wait_ms, false, None).await?;
+    response.text().await
+}
+
+/// Captures `url` as a screenshot (PNG) or PDF via the Browser Rendering binding and returns
+/// the raw binary bytes.
+pub async fn fetch_rendered_capture(
+    env: &Env,
+    action: &str,
+    url: &str,
+    wait_for_selector: Option<&str>,
+    wait_ms: Option<u32>,
+    full_page: bool,
+    clip_selector: Option<&str>,
+) -> worker::Result<Vec<u8>> {
+    let mut response = render_via_browser(
+        env,
+        action,
+        url,
+        wait_for_selector,
+        wait_ms,
+        full_page,
+        clip_selector,
+    )
+    .await?;
+    response.bytes().await
+}
+
+pub async fn fetch_url_with_timeout(url: &str, timeout_ms: u32) -> worker::Result<String> {
+    fetch_url_with_options(url, timeout_ms, &HashMap::new(), None, None, None).await
+}
+
+/// Like `fetch_url_with_timeout`, but merges `custom_headers` over the generated fingerprint
+/// headers, sets `Referer` when given, caps the body at `max_body_bytes`
+/// (`DEFAULT_MAX_BODY_BYTES` when `None`), and follows at most `max_redirects` redirects
+/// (`DEFAULT_MAX_REDIRECTS` when `None`).
+pub async fn fetch_url_with_options(
+    url: &str,
+    timeout_ms: u32,
+    custom_headers: &HashMap<String, String>,
+    referer: Option<&str>,
+    max_body_bytes: Option<usize>,
+    max_redirects: Option<u32>,
+) -> worker::Result<String> {
+    let cache = FingerprintCache::new(); // Or get from a static instance
+    let fingerprint = cache.get_random();
+    let (html, _set_cookie, _final_url) = fetch_with_fingerprint(
+        url,
+        timeout_ms,
+        &fingerprint,
+        None,
+        custom_headers,
+        referer,
+        max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES),
+        max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS),
+    )
+    .await?;
+    Ok(html)
+}
+
+/// Core fetch logic shared by one-off requests and `FetchSession`. Applies `fingerprint`'s
+/// headers, then `custom_headers` and `referer` (which take precedence on conflict), optionally
+/// replays `cookie_header` on the `Cookie` header, follows up to `max_redirects` redirects
+/// (resolving relative `Location` headers against the current URL and erroring out on a loop),
+/// skips non-HTML-like `Content-Type`s before downloading, and returns the page body (capped at
+/// `max_body_bytes`), any raw `Set-Cookie` values observed on the final response (split with
+/// `split_set_cookie_string`, since repeated headers come back comma-joined), and the final
+/// (post-redirect) URL.
+///
+/// This makes exactly one attempt per hop -- no retries on 429/403/503 here. Retrying transient
+/// failures is the caller's job (`crawl::fetch_with_retries` for the crawl path), so there's a
+/// single retry policy instead of this function silently retrying a few times internally before a
+/// caller's own retry loop retries *that* failure again on top.
+async fn fetch_with_fingerprint(
+    url: &str,
+    _timeout_ms: u32,
+    fingerprint: &BrowserFingerprint,
+    cookie_header: Option<&str>,
+    custom_headers: &HashMap<String, String>,
+    referer: Option<&str>,
+    max_body_bytes: usize,
+    max_redirects: u32,
+) -> worker::Result<(String, Vec<String>, String)> {
     // Note: Cloudflare Workers don't have explicit request timeouts like standard async runtimes.
     // The platform imposes its own limits. The timeout_ms parameter is kept for potential future use
     // or adaptation but isn't directly used in the Fetch API here.
 
     let mut opts = RequestInit::new();
     opts.method = Method::Get;
+    opts.redirect = RequestRedirect::Manual;
 
-    let cache = FingerprintCache::new(); // Or get from a static instance
-    let fingerprint = cache.get_random();
-    let mut headers = Headers::new(); // Create new headers
-    fingerprint.apply_to_headers(&mut headers)?; // Apply the fingerprint headers
-    opts.headers = headers; // Assign the generated headers to the request options
+    let mut headers = Headers::new();
+    fingerprint.apply_to_headers(&mut headers)?;
+    if let Some(cookie_header) = cookie_header {
+        if !cookie_header.is_empty() {
+            headers.set("Cookie", cookie_header)?;
+        }
+    }
+    if let Some(referer) = referer {
+        headers.set("Referer", referer)?;
+    }
+    for (name, value) in custom_headers {
+        if !is_valid_header(name, value) {
+            return Err(worker::Error::RustError(format!(
+                "Invalid custom header \"{}\"",
+                name
+            )));
+        }
+        headers.set(name, value)?;
+    }
+    opts.headers = headers;
 
     console_log!("Fetching URL: {}", url);
 
-    let mut retry_count = 0;
-    let max_retries = 3;
+    let mut current_url = url.to_string();
+    let mut redirect_count = 0u32;
+    let mut seen_hops: HashSet<String> = HashSet::new();
+    seen_hops.insert(current_url.clone());
 
     loop {
-        let request = Request::new_with_init(url, &opts)?;
-        let mut response = Fetch::Request(request).send().await?;
+        let request = Request::new_with_init(&current_url, &opts)?;
+        let response = Fetch::Request(request).send().await?;
+        let status = response.status_code();
 
-        if response.status_code() >= 400 {
-            if response.status_code() == 429 || response.status_code() == 403 {
-                if retry_count >= max_retries {
+        if (300..400).contains(&status) {
+            let location = response.headers().get("location")?;
+            let location = match location {
+                Some(location) => location,
+                None => {
                     return Err(worker::Error::RustError(format!(
-                        "Rate limit or access denied after {} retries for URL {}",
-                        max_retries, url
+                        "Redirect status {} with no Location header for {}",
+                        status, current_url
                     )));
                 }
-                console_error!("Rate limit or access denied for {}, retrying...", url);
-                worker::Delay::from(Duration::from_secs(2u64.pow(retry_count))).await; // Exponential backoff
-                retry_count += 1;
-                continue;
+            };
+
+            if redirect_count >= max_redirects {
+                return Err(worker::Error::RustError(format!(
+                    "Exceeded {} redirects starting from {}",
+                    max_redirects, url
+                )));
             }
 
-            if response.status_code() == 503 {
-                 // Often indicates service unavailable or sometimes CAPTCHA-like blocks
-                if retry_count >= max_retries {
-                    return Err(worker::Error::RustError(format!(
-                        "Service unavailable (503) after {} retries for URL {}",
-                         max_retries, url
-                    )));
-                }
-                console_error!("Service unavailable (503) for {}, retrying...", url);
-                worker::Delay::from(Duration::from_secs(3u64.pow(retry_count))).await; // Longer backoff for 503
-                retry_count += 1;
-                continue;
+            let next_url = Url::parse(&current_url)
+                .and_then(|base| base.join(&location))
+                .map_err(|e| {
+                    worker::Error::RustError(format!(
+                        "Invalid redirect Location '{}' from {}: {}",
+                        location, current_url, e
+                    ))
+                })?
+                .to_string();
+
+            if !seen_hops.insert(next_url.clone()) {
+                return Err(worker::Error::RustError(format!(
+                    "Redirect loop detected starting from {} at {}",
+                    url, next_url
+                )));
             }
 
-            console_error!("Fetch error on attempt {} for {}: Status {}", retry_count + 1, url, response.status_code());
+            console_log!("Following redirect {} -> {}", current_url, next_url);
+            current_url = next_url;
+            redirect_count += 1;
+            continue;
+        }
+
+        if status >= 400 {
+            console_error!("Fetch error for {}: Status {}", current_url, status);
             return Err(worker::Error::RustError(format!(
                 "HTTP error {} for URL {}",
-                response.status_code(), url
+                status, current_url
+            )));
+        }
+
+        let set_cookie = response
+            .headers()
+            .get("set-cookie")?
+            .map(|raw| split_set_cookie_string(&raw))
+            .unwrap_or_default();
+
+        let content_type = response.headers().get("content-type")?.unwrap_or_default();
+        if !is_convertible_content_type(&content_type) {
+            return Err(worker::Error::RustError(format!(
+                "Skipping non-HTML content-type '{}' for {}",
+                content_type, current_url
             )));
         }
 
-        match response.text().await {
-            Ok(text) => return Ok(text),
+        match read_body_capped(response, max_body_bytes).await {
+            Ok(text) => return Ok((text, set_cookie, current_url)),
             Err(e) => {
-                console_error!("Text extraction error for {}: {:?}", url, e);
-                // Don't retry on text extraction error, likely a non-HTML response or corrupted data
-                return Err(worker::Error::RustError(format!("Text extraction failed for {}: {}", url, e)));
+                console_error!("Body read error for {}: {:?}", current_url, e);
+                // Don't retry on a read/size error, likely a non-HTML response or corrupted data
+                return Err(worker::Error::RustError(format!("Body read failed for {}: {}", current_url, e)));
+            }
+        }
+    }
+}
+
+/// Pins one `BrowserFingerprint` and carries a per-host cookie jar for the lifetime of a crawl
+/// or multi-request session, so retries and repeat pages to the same site look like one
+/// consistent browser instead of a fresh identity every time. Single, one-off conversions should
+/// keep using `fetch_url_with_timeout`, which rotates fingerprints per request.
+pub struct FetchSession {
+    fingerprint: BrowserFingerprint,
+    // host -> (cookie name -> value)
+    cookie_jar: Mutex<HashMap<String, HashMap<String, String>>>,
+}
+
+impl FetchSession {
+    pub fn new() -> Self {
+        Self {
+            fingerprint: FingerprintCache::new().get_random(),
+            cookie_jar: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn fetch(&self, url: &str, timeout_ms: u32) -> worker::Result<String> {
+        let (html, _final_url) = self.fetch_with_options(url, timeout_ms, &HashMap::new(), None, None, None).await?;
+        Ok(html)
+    }
+
+    /// Like `fetch`, but also returns the final (post-redirect) URL, so callers that dedupe by
+    /// URL (e.g. the crawler's `visited` set) can recognize two entry points that redirect to the
+    /// same destination.
+    pub async fn fetch_with_options(
+        &self,
+        url: &str,
+        timeout_ms: u32,
+        custom_headers: &HashMap<String, String>,
+        referer: Option<&str>,
+        max_body_bytes: Option<usize>,
+        max_redirects: Option<u32>,
+    ) -> worker::Result<(String, String)> {
+        let host = Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_string));
+
+        let cookie_header = if let Some(ref host) = host {
+            let jar = self.cookie_jar.lock().await;
+            jar.get(host).map(|cookies| {
+                cookies
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            })
+        } else {
+            None
+        };
+
+        let (html, set_cookie, final_url) = fetch_with_fingerprint(
+            url,
+            timeout_ms,
+            &self.fingerprint,
+            cookie_header.as_deref(),
+            custom_headers,
+            referer,
+            max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES),
+            max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS),
+        )
+        .await?;
+
+        if let Some(host) = host {
+            if !set_cookie.is_empty() {
+                let mut jar = self.cookie_jar.lock().await;
+                let host_cookies = jar.entry(host).or_insert_with(HashMap::new);
+                for raw_cookie in set_cookie {
+                    if let Some(pair) = raw_cookie.split(';').next() {
+                        if let Some((name, value)) = pair.split_once('=') {
+                            host_cookies.insert(name.trim().to_string(), value.trim().to_string());
+                        }
+                    }
+                }
             }
         }
+
+        Ok((html, final_url))
     }
 }
\ No newline at end of file
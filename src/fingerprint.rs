@@ -2,6 +2,19 @@ use lazy_static::lazy_static;
 use rand::{rngs::SmallRng, SeedableRng, Rng, seq::SliceRandom};
 use js_sys::Date;
 use worker::Headers;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Per-process call counter mixed into the RNG seed so that two fingerprints requested in the
+// same millisecond (e.g. the ten pre-warmed by `FingerprintCache::new`) don't come out identical.
+static ENTROPY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn seeded_rng() -> SmallRng {
+    let now = Date::now() as u64;
+    let counter = ENTROPY_COUNTER.fetch_add(1, Ordering::Relaxed);
+    // Cheap splitmix-style mix so nearby (now, counter) pairs don't produce nearby seeds.
+    let seed = (now.wrapping_mul(0x9E3779B97F4A7C15)) ^ counter.wrapping_mul(0xBF58476D1CE4E5B9);
+    SmallRng::seed_from_u64(seed)
+}
 
 #[derive(Debug, Clone)]
 pub struct BrowserVersion {
@@ -12,7 +25,6 @@ pub struct BrowserVersion {
     pub platform: &'static str,
     pub engine: &'static str,
     pub engine_version: &'static str,
-    pub brand_version: String,
 }
 
 #[derive(Debug, Clone)]
@@ -31,7 +43,13 @@ pub struct BrowserFingerprint {
     pub architecture: &'static str,
     pub bitness: &'static str,
     pub platform_version: String,
+    /// Full dotted version string for the chosen major (e.g. "120.0.6099.109"), threaded
+    /// through the User-Agent, `Sec-CH-UA-Full-Version` and `Sec-CH-UA-Full-Version-List`.
     pub browser_version: String,
+    /// `Sec-CH-UA` brand list (GREASE + real brand), empty for non-Chromium engines.
+    pub brand_list: String,
+    /// `Sec-CH-UA-Full-Version-List` brand list with full versions, empty for non-Chromium engines.
+    pub full_version_list: String,
     pub mobile: bool,
     pub headers: Vec<(String, String)>,
     pub connection_type: &'static str,
@@ -44,21 +62,19 @@ lazy_static! {
             name: "Chrome",
             version_prefix: "Chrome/",
             min_version: 90,
-            max_version: 119,
+            max_version: 124,
             platform: "Windows",
             engine: "Blink",
             engine_version: "90.0.0.0",
-            brand_version: "90.0.6099.109".to_string(),
         },
         BrowserVersion {
             name: "Firefox",
             version_prefix: "Firefox/",
             min_version: 90,
-            max_version: 119,
+            max_version: 124,
             platform: "Windows",
             engine: "Gecko",
             engine_version: "90.0",
-            brand_version: "90.0".to_string(),
         },
         BrowserVersion {
             name: "Safari",
@@ -68,7 +84,6 @@ lazy_static! {
             platform: "Macintosh",
             engine: "WebKit",
             engine_version: "15.0",
-            brand_version: "15.0".to_string(),
         }
     ];
 
@@ -94,12 +109,19 @@ lazy_static! {
         "aarch64", "x86_64",
     ];
 
-    static ref PLATFORM_VERSIONS: Vec<&'static str> = vec![
+    // macOS-style underscored versions, used in the User-Agent's platform token.
+    static ref MACOS_PLATFORM_VERSIONS: Vec<&'static str> = vec![
         "10_15_7", "11_0_0", "11_2_3", "11_3_1", "11_4_0", "11_5_2", "11_6_0",
         "12_0_0", "12_1_0", "12_2_1", "12_3_0", "12_4_0", "12_5_0", "12_6_0",
         "13_0_0", "13_1_0", "13_2_0", "13_3_0", "13_4_0", "13_5_0",
     ];
 
+    // Dotted Windows versions reported via `Sec-CH-UA-Platform-Version` (10.0.x = Windows 10,
+    // 15.0.x = Windows 11, per the client-hints platform version mapping).
+    static ref WINDOWS_PLATFORM_VERSIONS: Vec<&'static str> = vec![
+        "10.0.0", "10.0.19044", "10.0.19045", "15.0.0", "15.0.1", "15.0.2",
+    ];
+
     static ref CONNECTION_TYPES: Vec<&'static str> = vec![
         "wifi", "4g", "3g",
     ];
@@ -107,11 +129,26 @@ lazy_static! {
 
 impl BrowserFingerprint {
     pub fn generate() -> Self {
-        let now = Date::now() as u64;
-        let mut rng = SmallRng::seed_from_u64(now);
+        let mut rng = seeded_rng();
 
         let browser = BROWSER_CONFIGS.choose(&mut rng).unwrap().clone();
 
+        // Pick one major version for this fingerprint and thread it through every surface
+        // (User-Agent, Sec-CH-UA, Sec-CH-UA-Full-Version, Sec-CH-UA-Full-Version-List) so they
+        // can't disagree with each other.
+        let major_version = rng.gen_range(browser.min_version..=browser.max_version);
+        let full_version = match browser.name {
+            "Chrome" => format!(
+                "{}.0.{}.{}",
+                major_version,
+                rng.gen_range(4000..7000),
+                rng.gen_range(50..200)
+            ),
+            "Firefox" => format!("{}.0", major_version),
+            "Safari" => format!("{}.{}", major_version, rng.gen_range(0..3)),
+            _ => unreachable!(),
+        };
+
         let viewport = VIEWPORT_SIZES.choose(&mut rng).unwrap();
 
         let color_depth = if rng.gen_bool(0.9) { 24 } else { 32 };
@@ -129,13 +166,35 @@ impl BrowserFingerprint {
 
         let memory_gb = [4, 8, 16, 32].choose(&mut rng).unwrap().clone();
 
-        let (webgl_renderer, webgl_vendor) = WEBGL_CONFIGS.choose(&mut rng).unwrap();
+        let (webgl_vendor, webgl_renderer) = WEBGL_CONFIGS.choose(&mut rng).unwrap();
 
         let mut preferred_languages = vec![LANGUAGES.choose(&mut rng).unwrap().to_string()];
         if rng.gen_bool(0.3) {
             preferred_languages.push("en-US,en;q=0.9".to_string());
         }
 
+        let is_chromium = browser.engine == "Blink";
+        let (brand_list, full_version_list) = if is_chromium {
+            (
+                format!(
+                    "\"Not A(Brand\";v=\"99\", \"Chromium\";v=\"{major}\", \"Google Chrome\";v=\"{major}\"",
+                    major = major_version
+                ),
+                format!(
+                    "\"Not A(Brand\";v=\"99.0.0.0\", \"Chromium\";v=\"{full}\", \"Google Chrome\";v=\"{full}\"",
+                    full = full_version
+                ),
+            )
+        } else {
+            (String::new(), String::new())
+        };
+
+        let platform_version = if browser.platform == "Windows" {
+            WINDOWS_PLATFORM_VERSIONS.choose(&mut rng).unwrap().to_string()
+        } else {
+            MACOS_PLATFORM_VERSIONS.choose(&mut rng).unwrap().to_string()
+        };
+
         let mut instance = Self {
             user_agent: String::new(),
             accept_language: preferred_languages[0].clone(),
@@ -150,15 +209,17 @@ impl BrowserFingerprint {
             webgl_renderer: webgl_renderer.to_string(),
             architecture: ARCHITECTURES.choose(&mut rng).unwrap(),
             bitness: if browser.platform == "Windows" { "64" } else { "32" },
-            platform_version: PLATFORM_VERSIONS.choose(&mut rng).unwrap().to_string(),
-            browser_version: format!("{}.{}", browser.min_version, browser.max_version),
+            platform_version,
+            browser_version: full_version,
+            brand_list,
+            full_version_list,
             mobile: false,
             headers: Vec::with_capacity(20),
             connection_type: CONNECTION_TYPES.choose(&mut rng).unwrap(),
             preferred_languages,
         };
 
-        instance.user_agent = instance.generate_user_agent(&browser);
+        instance.user_agent = instance.generate_user_agent(&browser, major_version);
         instance.generate_headers(&browser);
 
         instance
@@ -168,20 +229,24 @@ impl BrowserFingerprint {
         headers.set("User-Agent", &self.user_agent)?;
         headers.set("Accept-Language", &self.accept_language)?;
 
-        headers.set("Sec-CH-UA-Platform-Version", &self.platform_version)?;
-        headers.set("Sec-CH-UA-Model", "")?;
-        headers.set("Sec-CH-UA-Mobile", if self.mobile { "?1" } else { "?0" })?;
+        let is_chromium = !self.brand_list.is_empty();
+        if is_chromium {
+            headers.set("Sec-CH-UA", &self.brand_list)?;
+            headers.set("Sec-CH-UA-Full-Version-List", &self.full_version_list)?;
+            headers.set("Sec-CH-UA-Full-Version", &self.browser_version)?;
+            headers.set("Sec-CH-UA-Platform", &format!("\"{}\"", self.platform))?;
+            headers.set("Sec-CH-UA-Platform-Version", &format!("\"{}\"", self.platform_version))?;
+            headers.set("Sec-CH-UA-Model", "\"\"")?;
+            headers.set("Sec-CH-UA-Mobile", if self.mobile { "?1" } else { "?0" })?;
+            headers.set("Sec-CH-UA-Arch", &format!("\"{}\"", self.architecture))?;
+            headers.set("Sec-CH-UA-Bitness", &format!("\"{}\"", self.bitness))?;
+            headers.set("Sec-CH-UA-WebGL-Vendor", &self.webgl_vendor)?;
+            headers.set("Sec-CH-UA-WebGL-Renderer", &self.webgl_renderer)?;
+        }
 
         headers.set("Viewport-Width", &self.viewport.0.to_string())?;
         headers.set("Width", &self.viewport.0.to_string())?;
         headers.set("Device-Memory", &self.memory_gb.to_string())?;
-        headers.set("Sec-CH-UA-Full-Version", &self.browser_version)?;
-
-        headers.set("Sec-CH-UA-WebGL-Vendor", &self.webgl_vendor)?;
-        headers.set("Sec-CH-UA-WebGL-Renderer", &self.webgl_renderer)?;
-
-        headers.set("Sec-CH-UA-Arch", self.architecture)?;
-        headers.set("Sec-CH-UA-Bitness", self.bitness)?;
 
         headers.set("Downlink", "10.0")?;
         headers.set("ECT", self.connection_type)?;
@@ -201,71 +266,41 @@ impl BrowserFingerprint {
         Ok(())
     }
 
-    fn generate_user_agent(&self, browser: &BrowserVersion) -> String {
+    fn generate_user_agent(&self, browser: &BrowserVersion, major_version: u32) -> String {
+        let platform_token = if self.platform == "Windows" {
+            "Windows NT 10.0; Win64; x64".to_string()
+        } else {
+            format!("Macintosh; Intel Mac OS X {}", self.platform_version)
+        };
+
         match browser.name {
             "Chrome" => format!(
-                "Mozilla/5.0 ({}) {} {} {} Safari/537.36",
-                if self.platform == "Windows" {
-                    format!("Windows NT {}; Win64; x64", self.platform_version)
-                } else {
-                    format!("Macintosh; Intel Mac OS X {}", self.platform_version)
-                },
-                browser.engine,
-                browser.version_prefix,
-                browser.min_version
+                "Mozilla/5.0 ({}) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/{} Safari/537.36",
+                platform_token, self.browser_version
             ),
             "Firefox" => format!(
-                "Mozilla/5.0 ({}) Gecko/{} Firefox/{}",
-                if self.platform == "Windows" {
-                    format!("Windows NT {}; Win64; x64", self.platform_version)
-                } else {
-                    format!("Macintosh; Intel Mac OS X {}", self.platform_version)
-                },
+                "Mozilla/5.0 ({}; rv:{major}.0) Gecko/{} Firefox/{major}.0",
+                platform_token,
                 browser.engine_version,
-                browser.min_version
+                major = major_version
             ),
             "Safari" => format!(
                 "Mozilla/5.0 ({}) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/{} Safari/605.1.15",
-                if self.platform == "Windows" {
-                    format!("Windows NT {}; Win64; x64", self.platform_version)
-                } else {
-                    format!("Macintosh; Intel Mac OS X {}", self.platform_version)
-                },
-                browser.min_version
+                platform_token, self.browser_version
             ),
             _ => unreachable!(),
         }
     }
 
-    fn generate_headers(&mut self, browser: &BrowserVersion) {
+    // Baseline headers common to every engine; Sec-CH-UA* hints are applied directly from
+    // struct fields in `apply_to_headers` since they only make sense for Chromium.
+    fn generate_headers(&mut self, _browser: &BrowserVersion) {
         self.headers.clear();
 
-        self.headers.push(("User-Agent".to_string(), self.user_agent.clone()));
-        self.headers.push(("Accept-Language".to_string(), self.accept_language.clone()));
         self.headers.push(("Accept".to_string(), "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8".to_string()));
         self.headers.push(("Accept-Encoding".to_string(), "gzip, deflate, br".to_string()));
         self.headers.push(("Connection".to_string(), "keep-alive".to_string()));
-
-        if browser.name == "Chrome" {
-            self.headers.push(("Sec-CH-UA".to_string(), browser.brand_version.clone()));
-            self.headers.push(("Sec-CH-UA-Mobile".to_string(), "?0".to_string()));
-            self.headers.push(("Sec-CH-UA-Platform".to_string(), format!("\"{}\"", self.platform)));
-            self.headers.push(("Sec-CH-UA-Arch".to_string(), format!("\"{}\"", self.architecture)));
-            self.headers.push(("Sec-CH-UA-Bitness".to_string(), format!("\"{}\"", self.bitness)));
-            self.headers.push(("Sec-CH-UA-Full-Version-List".to_string(), browser.brand_version.clone()));
-            self.headers.push(("Device-Memory".to_string(), format!("{}", self.memory_gb)));
-            self.headers.push(("Sec-CH-UA-Model".to_string(), "".to_string()));
-            self.headers.push(("Color-Depth".to_string(), self.color_depth.to_string()));
-            self.headers.push(("Hardware-Concurrency".to_string(), self.hardware_concurrency.to_string()));
-
-        }
-
-        self.headers.push(("Viewport-Width".to_string(), self.viewport.0.to_string()));
         self.headers.push(("DPR".to_string(), format!("{:.1}", self.pixel_ratio)));
-        self.headers.push(("Device-Memory".to_string(), format!("{}", self.memory_gb)));
-        self.headers.push(("RTT".to_string(), "50".to_string()));
-        self.headers.push(("Downlink".to_string(), "10.0".to_string()));
-        self.headers.push(("ECT".to_string(), self.connection_type.to_string()));
     }
 }
 
@@ -283,8 +318,7 @@ impl FingerprintCache {
     }
 
     pub fn get_random(&self) -> BrowserFingerprint {
-        let now = Date::now() as u64;
-        let mut rng = SmallRng::seed_from_u64(now);
+        let mut rng = seeded_rng();
         if rng.gen_bool(0.1) {
             BrowserFingerprint::generate()
         } else {
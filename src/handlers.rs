@@ -1,48 +1,238 @@
 use worker::*;
-use crate::config::ConvertRequest;
-use crate::fetch::fetch_url_with_timeout;
+use crate::config::{ConvertJsonResult, ConvertRequest, CrawlRequest, HtmlConversionResult, OutputFormat};
+use crate::crawl::{handle_crawl, handle_crawl_stream};
+use crate::fetch::{fetch_rendered_capture, fetch_rendered_html, fetch_url_with_options};
 use crate::markdown::html_to_markdown;
-use crate::utils::add_cors_headers;
+use crate::utils::{add_cors_headers, cors_config, compute_etag, etag_matches};
 
-pub async fn handle_conversion_request(mut req: Request) -> worker::Result<Response> {
+pub async fn handle_conversion_request(mut req: Request, env: Env) -> worker::Result<Response> {
+    let origin = req.headers().get("Origin")?;
+    let if_none_match = req.headers().get("If-None-Match")?;
+    let accept = req.headers().get("Accept")?;
     let request: ConvertRequest = match req.json().await {
         Ok(req_data) => req_data,
         Err(e) => {
             console_error!("JSON parsing error: {:?}", e);
             let resp = Response::error(format!("Invalid request format: {}", e), 400)?;
-            return add_cors_headers(resp);
+            return add_cors_headers(resp, origin.as_deref(), &cors_config(&env));
         }
     };
-    handle_conversion(request).await
+    handle_conversion(request, env, origin, if_none_match, accept).await
 }
 
-pub async fn handle_conversion(request: ConvertRequest) -> worker::Result<Response> {
+/// True when the client's `Accept` header mentions `application/json`, requesting the structured
+/// `{ url, markdown, metadata }` body (`ConvertJsonResult`) instead of bare Markdown.
+pub(crate) fn wants_json(accept: Option<&str>) -> bool {
+    accept.is_some_and(|value| value.contains("application/json"))
+}
+
+pub async fn handle_conversion(request: ConvertRequest, env: Env, origin: Option<String>, if_none_match: Option<String>, accept: Option<String>) -> worker::Result<Response> {
     let url_for_logging = request.url.clone();
     console_log!("Processing URL: {}", url_for_logging);
+    let allowed = cors_config(&env);
+    let cache_control = format!("public, max-age={}", request.config.cache_ttl_seconds.unwrap_or(300));
+
+    if request.config.output != OutputFormat::Markdown {
+        return match capture_page(&request, env).await {
+            Ok((bytes, content_type)) => {
+                let etag = compute_etag(&bytes);
+                if if_none_match.as_deref().is_some_and(|value| etag_matches(value, &etag)) {
+                    return add_cors_headers(not_modified(&etag)?, origin.as_deref(), &allowed);
+                }
+                let headers = Headers::from_iter([
+                    ("Cache-Control", "no-store"),
+                    ("Content-Type", content_type),
+                    ("ETag", etag.as_str()),
+                ]);
+                let resp = Response::from_bytes(bytes)?.with_headers(headers);
+                add_cors_headers(resp, origin.as_deref(), &allowed)
+            }
+            Err(e) => {
+                console_error!("Error during capture for {}: {}", url_for_logging, e);
+                let error_message = format!("Failed to capture URL '{}': {}", url_for_logging, e);
+                let resp = Response::error(error_message, 500)?;
+                add_cors_headers(resp, origin.as_deref(), &allowed)
+            }
+        };
+    }
+
+    match fetch_and_convert(request, env).await {
+        Ok(result) => {
+            if wants_json(accept.as_deref()) {
+                let json_result = ConvertJsonResult {
+                    url: url_for_logging.clone(),
+                    markdown: result.markdown.clone(),
+                    metadata: result.metadata,
+                };
+                let body = serde_json::to_string(&json_result)
+                    .map_err(|e| Error::RustError(format!("Failed to serialize conversion result: {}", e)))?;
+                let etag = compute_etag(body.as_bytes());
+                if if_none_match.as_deref().is_some_and(|value| etag_matches(value, &etag)) {
+                    return add_cors_headers(not_modified(&etag)?, origin.as_deref(), &allowed);
+                }
+                let headers = Headers::from_iter([
+                    ("Cache-Control", cache_control.as_str()),
+                    ("Content-Type", "application/json; charset=utf-8"),
+                    ("ETag", etag.as_str()),
+                ]);
+                let resp = Response::ok(body)?.with_headers(headers);
+                return add_cors_headers(resp, origin.as_deref(), &allowed);
+            }
 
-    match fetch_and_convert(request).await {
-        Ok(markdown) => {
+            let etag = compute_etag(result.markdown.as_bytes());
+            if if_none_match.as_deref().is_some_and(|value| etag_matches(value, &etag)) {
+                return add_cors_headers(not_modified(&etag)?, origin.as_deref(), &allowed);
+            }
              let headers = Headers::from_iter([
-                ("Cache-Control", "no-store"),
+                ("Cache-Control", cache_control.as_str()),
                 ("Content-Type", "text/markdown; charset=utf-8"),
+                ("ETag", etag.as_str()),
             ]);
-            let resp = Response::ok(markdown)?.with_headers(headers);
-            add_cors_headers(resp)
+            let resp = Response::ok(result.markdown)?.with_headers(headers);
+            add_cors_headers(resp, origin.as_deref(), &allowed)
         },
         Err(e) => {
             console_error!("Error during conversion for {}: {}", url_for_logging, e);
              let status = if e.to_string().contains("HTTP error 404") { 404 }
-                         else if e.to_string().contains("HTTP error 403") || e.to_string().contains("access denied") { 403 }
-                         else if e.to_string().contains("HTTP error 503") || e.to_string().contains("Service unavailable") { 503 }
+                         else if e.to_string().contains("HTTP error 403") { 403 }
+                         else if e.to_string().contains("HTTP error 503") { 503 }
+                         else if e.to_string().contains("Invalid custom header") { 400 }
                          else { 500 };
              let error_message = format!("Failed to fetch or convert URL '{}': {}", url_for_logging, e);
              let resp = Response::error(error_message, status)?;
-             add_cors_headers(resp)
+             add_cors_headers(resp, origin.as_deref(), &allowed)
+        }
+    }
+}
+
+/// Builds a bodyless `304 Not Modified` response carrying the matched `ETag`, per RFC 9110 section 15.4.5.
+fn not_modified(etag: &str) -> worker::Result<Response> {
+    let headers = Headers::from_iter([("ETag", etag)]);
+    Ok(Response::empty()?.with_status(304).with_headers(headers))
+}
+
+async fn capture_page(request: &ConvertRequest, env: Env) -> worker::Result<(Vec<u8>, &'static str)> {
+    let action = match request.config.output {
+        OutputFormat::Png => "screenshot",
+        OutputFormat::Pdf => "pdf",
+        OutputFormat::Markdown => unreachable!("caller only invokes capture_page for non-Markdown output"),
+    };
+    let content_type = match request.config.output {
+        OutputFormat::Png => "image/png",
+        OutputFormat::Pdf => "application/pdf",
+        OutputFormat::Markdown => unreachable!(),
+    };
+
+    let bytes = fetch_rendered_capture(
+        &env,
+        action,
+        &request.url,
+        request.config.wait_for_selector.as_deref(),
+        request.config.wait_ms,
+        request.config.full_page,
+        request.config.clip_selector.as_deref(),
+    )
+    .await?;
+
+    Ok((bytes, content_type))
+}
+
+/// True when the client's `Accept` header asks for newline-delimited JSON, the content type a
+/// streamed crawl is served as.
+fn wants_ndjson(accept: Option<&str>) -> bool {
+    accept.is_some_and(|value| value.contains("application/x-ndjson"))
+}
+
+pub async fn handle_crawl_request(mut req: Request, env: Env) -> worker::Result<Response> {
+    let origin = req.headers().get("Origin")?;
+    let accept = req.headers().get("Accept")?;
+    let allowed = cors_config(&env);
+
+    let request: CrawlRequest = match req.json().await {
+        Ok(req_data) => req_data,
+        Err(e) => {
+            console_error!("Crawl request parsing error: {:?}", e);
+            let resp = Response::error(format!("Invalid crawl request: {}", e), 400)?;
+            return add_cors_headers(resp, origin.as_deref(), &allowed);
+        }
+    };
+
+    if request.stream || wants_ndjson(accept.as_deref()) {
+        return match handle_crawl_stream(request).await {
+            Ok(stream) => {
+                let mut resp = Response::from_stream(stream)?;
+                resp.headers_mut().set("Content-Type", "application/x-ndjson; charset=utf-8")?;
+                resp.headers_mut().set("Cache-Control", "no-store")?;
+                add_cors_headers(resp, origin.as_deref(), &allowed)
+            }
+            Err(e) => {
+                console_error!("Crawl stream setup error: {}", e);
+                let resp = Response::error(format!("Crawl failed: {}", e), 500)?;
+                add_cors_headers(resp, origin.as_deref(), &allowed)
+            }
+        };
+    }
+
+    let want_report = request.report;
+
+    match handle_crawl(request).await {
+        Ok(report) => {
+            let body = if want_report {
+                serde_json::to_string(&report)
+            } else {
+                serde_json::to_string(&report.results)
+            }
+            .map_err(|e| Error::RustError(format!("Failed to serialize crawl results: {}", e)))?;
+            let headers = Headers::from_iter([
+                ("Cache-Control", "no-cache"),
+                ("Content-Type", "application/json; charset=utf-8"),
+            ]);
+            let resp = Response::ok(body)?.with_headers(headers);
+            add_cors_headers(resp, origin.as_deref(), &allowed)
+        }
+        Err(e) => {
+            console_error!("Crawl handler error: {}", e);
+            let resp = Response::error(format!("Crawl failed: {}", e), 500)?;
+            add_cors_headers(resp, origin.as_deref(), &allowed)
         }
     }
 }
 
-async fn fetch_and_convert(req: ConvertRequest) -> worker::Result<String> {
-    let html = fetch_url_with_timeout(&req.url, 10000).await?;
-    Ok(html_to_markdown(&html, req.config).markdown)
+async fn fetch_and_convert(req: ConvertRequest, env: Env) -> worker::Result<HtmlConversionResult> {
+    let html = if req.config.render {
+        match fetch_rendered_html(
+            &env,
+            &req.url,
+            req.config.wait_for_selector.as_deref(),
+            req.config.wait_ms,
+        )
+        .await
+        {
+            Ok(html) => html,
+            Err(e) => {
+                console_error!("Browser rendering unavailable for {}, falling back to plain fetch: {}", req.url, e);
+                fetch_url_with_options(
+                    &req.url,
+                    10000,
+                    &req.config.custom_headers,
+                    req.config.referer.as_deref(),
+                    req.config.max_body_bytes.map(|v| v as usize),
+                    req.config.max_redirects,
+                )
+                .await?
+            }
+        }
+    } else {
+        fetch_url_with_options(
+            &req.url,
+            10000,
+            &req.config.custom_headers,
+            req.config.referer.as_deref(),
+            req.config.max_body_bytes.map(|v| v as usize),
+            req.config.max_redirects,
+        )
+        .await?
+    };
+
+    Ok(html_to_markdown(&html, req.config))
 }
\ No newline at end of file
@@ -1,70 +1,45 @@
 #![recursion_limit = "512"]
 
-mod config;
+pub mod config;
 mod crawl;
+pub mod event;
 mod fetch;
 mod fingerprint;
 mod handlers;
-mod markdown;
-mod metadata;
+pub mod markdown;
+pub mod metadata;
+pub mod node_handler;
+mod robots;
 mod utils;
 
 use worker::*;
 use console_error_panic_hook;
 
-use crate::config::CrawlRequest;
-use crate::crawl::handle_crawl;
-use crate::handlers::{handle_conversion_request, handle_conversion};
+use crate::handlers::{handle_conversion_request, handle_conversion, handle_crawl_request, wants_json};
 
 #[worker::event(fetch)]
-pub async fn main(mut req: Request, _env: Env, _ctx: Context) -> Result<Response> {
+pub async fn main(mut req: Request, env: Env, ctx: Context) -> Result<Response> {
     console_error_panic_hook::set_once();
 
     let url = req.url()?;
     let path = url.path();
+    let origin = req.headers().get("Origin")?;
+    let if_none_match = req.headers().get("If-None-Match")?;
+    let accept = req.headers().get("Accept")?;
+    let allowed = utils::cors_config(&env);
 
     if req.method() == Method::Options {
-        return utils::handle_options_request();
+        return utils::handle_options_request(origin.as_deref(), &allowed);
     }
 
     if path == "/favicon.ico" {
         let resp = Response::empty()?.with_status(204);
-        return utils::add_cors_headers(resp);
+        return utils::add_cors_headers(resp, origin.as_deref(), &allowed);
     }
 
     match (req.method(), path) {
         (Method::Post, "/crawl") => {
-            match req.json::<CrawlRequest>().await {
-                Ok(crawl_req) => {
-                    match handle_crawl(crawl_req).await {
-                        Ok(results) => {
-                            if results.is_empty() {
-                                let mut resp = Response::ok("Crawl completed, but no results were generated.")?;
-                                resp.headers_mut().set("Content-Type", "text/plain; charset=utf-8")?;
-                                resp.headers_mut().set("Cache-Control", "no-cache")?;
-                                utils::add_cors_headers(resp)
-                            } else {
-                                let separator = "\n\n---\n\n";
-                                let combined_markdown = results.join(separator);
-                                let mut resp = Response::ok(combined_markdown)?;
-                                resp.headers_mut().set("Content-Type", "text/markdown; charset=utf-8")?;
-                                resp.headers_mut().set("Cache-Control", "no-cache")?;
-                                utils::add_cors_headers(resp)
-                            }
-                        }
-                        Err(e) => {
-                            console_error!("Crawl handler error: {}", e);
-                            let resp = Response::error(format!("Crawl failed: {}", e), 500)?;
-                            utils::add_cors_headers(resp)
-                        }
-                    }
-                },
-                Err(e) => {
-                    console_error!("Crawl request parsing error: {}", e);
-                    let resp = Response::error(format!("Invalid crawl request: {}", e), 400)?;
-                    utils::add_cors_headers(resp)
-                }
-            }
+            handle_crawl_request(req, env).await
         },
 
         (Method::Get, path) if path.starts_with("/http://") || path.starts_with("/https://") => {
@@ -75,39 +50,72 @@ pub async fn main(mut req: Request, _env: Env, _ctx: Context) -> Result<Response
 
             console_log!("GET request for URL in path: {}", target_url);
 
-            let request = config::ConvertRequest {
-                url: target_url,
-                config: config::ConvertConfig {
-                    include_links: true,
-                    clean_whitespace: true,
-                    cleaning_rules: config::CleaningRules {
-                        remove_scripts: true,
-                        remove_styles: true,
-                        remove_comments: true,
-                        preserve_line_breaks: true,
-                    },
-                    preserve_headings: true,
-                    include_metadata: true,
-                    max_heading_level: 6,
-                },
+            // The converted output only depends on the request URL (target + our own query-string
+            // config) plus the Accept header, and the Cache API keys purely on URL -- so the edge
+            // cache is only safe to use for the plain-Markdown case; a JSON-negotiated request
+            // always goes through the handler instead of risking a stale content-type from cache.
+            // `?no_cache=1` (or a `Cache-Control: no-cache` request header) skips both the lookup
+            // and the write-back, for a caller that needs to force a fresh fetch.
+            let no_cache = url.query_pairs().any(|(k, v)| k == "no_cache" && (v == "1" || v == "true"))
+                || req.headers().get("Cache-Control")?.is_some_and(|v| v.contains("no-cache"));
+            let wants_markdown_cache = !wants_json(accept.as_deref()) && !no_cache;
+            let cache = Cache::default();
+            // The Cache API keys purely on the request URL, not on any header -- so with a
+            // non-wildcard allow-list, folding `Origin` into the key is required, or whichever
+            // Origin first populates an entry has its echoed `Access-Control-Allow-Origin` served
+            // to every other Origin that requests the same URL (`Vary: Origin` only affects
+            // downstream HTTP caches, not this lookup).
+            let cache_key = {
+                let mut key_req = req.clone()?;
+                if !allowed.allowed_origins.iter().any(|o| o == "*") {
+                    let mut key_url = key_req.url()?;
+                    key_url.query_pairs_mut().append_pair("__cors_origin", origin.as_deref().unwrap_or(""));
+                    key_req = Request::new(key_url.as_str(), Method::Get)?;
+                }
+                key_req
+            };
+            if wants_markdown_cache {
+                if let Some(cached) = cache.get(&cache_key, true).await? {
+                    return Ok(cached);
+                }
+            }
+
+            let query = url.query_pairs().map(|(k, v)| (k.into_owned(), v.into_owned()));
+            let config = match config::ConvertConfig::from_query_defaults(query) {
+                Ok(config) => config,
+                Err(message) => {
+                    let resp = Response::error(message, 400)?;
+                    return utils::add_cors_headers(resp, origin.as_deref(), &allowed);
+                }
             };
-            handle_conversion(request).await
+            let request = config::ConvertRequest { url: target_url, config };
+            let resp = handle_conversion(request, env, origin, if_none_match, accept).await?;
+
+            if wants_markdown_cache && resp.status_code() == 200 {
+                if let Ok(cached_copy) = resp.cloned() {
+                    ctx.wait_until(async move {
+                        let _ = Cache::default().put(cache_key, cached_copy).await;
+                    });
+                }
+            }
+
+            Ok(resp)
         },
 
         (Method::Get, "/") => {
-             let mut resp = Response::ok("Usage: \nGET /{URL} (e.g., /https://example.com)\nPOST / { \"url\": \"https://example.com\", \"config\": {...} }\nPOST /crawl { \"url\": \"...\", \"limit\": N, ... }")?;
+             let mut resp = Response::ok("Usage: \nGET /{URL} (e.g., /https://example.com)\nPOST / { \"url\": \"https://example.com\", \"config\": {...} }\nPOST /crawl { \"url\": \"...\", \"limit\": N, ... } -> JSON array of { url, markdown, depth }")?;
              resp.headers_mut().set("Content-Type", "text/plain; charset=utf-8")?;
              resp.headers_mut().set("Cache-Control", "no-store")?;
-             utils::add_cors_headers(resp)
+             utils::add_cors_headers(resp, origin.as_deref(), &allowed)
         },
 
         (Method::Post, "/") => {
-            handle_conversion_request(req).await
+            handle_conversion_request(req, env).await
         },
 
         _ => {
             let resp = Response::error("Not Found", 404)?;
-            utils::add_cors_headers(resp)
+            utils::add_cors_headers(resp, origin.as_deref(), &allowed)
         }
     }
 }
\ No newline at end of file
@@ -1,154 +1,79 @@
 use html5ever::parse_document;
 use html5ever::tendril::TendrilSink;
 use markup5ever_rcdom::{Handle, NodeData, RcDom};
-use lazy_static::lazy_static;
-use std::collections::HashMap;
 use std::borrow::Cow;
 use std::cell::RefCell;
-use regex::Regex;
-use crate::config::{ConvertConfig, HtmlConversionResult};
+use std::sync::Arc;
+use crate::config::{ConvertConfig, ConvertMetadata, DefinitionListStyle, HtmlConversionResult, LinkStyle};
+use crate::event::{Alignment, Atom, Container, Event, ListKind};
 use crate::metadata::MetadataHandler;
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum BlockType {
-    Paragraph,
-    Header(u8),
-    List(ListType),
-    CodeBlock,
-    Table,
-    Quote,
-    Pre,
-    Div,
-    Article,
-    Section,
-    TableRow,
-    TableCell,
-    TableHeader,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum ListType {
-    Ordered(u8),
-    Unordered,
+use crate::node_handler::{DefaultNodeHandler, NodeHandler};
+
+/// Maps an inline HTML tag to the `Container` wrapping its rendered form. `<code>` is handled
+/// separately in `Parser::process_node` since it also toggles whitespace-preservation.
+fn inline_container(tag: &str) -> Option<Container> {
+    match tag {
+        "strong" | "b" => Some(Container::Strong),
+        "em" | "i" => Some(Container::Emphasis),
+        "mark" => Some(Container::Mark),
+        "del" => Some(Container::Strikethrough),
+        "ins" => Some(Container::Underline),
+        _ => None,
+    }
 }
 
-lazy_static! {
-    static ref INLINE_TAGS: HashMap<&'static str, (&'static str, &'static str)> = {
-        let mut m = HashMap::new();
-        m.insert("strong", ("**", "**"));
-        m.insert("b", ("**", "**"));
-        m.insert("em", ("*", "*"));
-        m.insert("i", ("*", "*"));
-        m.insert("code", ("`", "`"));
-        m.insert("mark", ("==", "=="));
-        m.insert("del", ("~~", "~~"));
-        m.insert("ins", ("__", "__"));
-        m
-    };
-
-    static ref BLOCK_TAGS: HashMap<&'static str, BlockType> = {
-        let mut m = HashMap::new();
-        m.insert("p", BlockType::Paragraph);
-        m.insert("div", BlockType::Div);
-        m.insert("article", BlockType::Article);
-        m.insert("section", BlockType::Section);
-        m.insert("table", BlockType::Table);
-        m.insert("tr", BlockType::TableRow);
-        m.insert("td", BlockType::TableCell);
-        m.insert("th", BlockType::TableHeader);
-        m
-    };
-     static ref WHITESPACE_REGEX: Regex = Regex::new(r"\s+").unwrap();
+/// Tags that carry no Markdown semantics of their own but should still be separated from
+/// surrounding content by blank lines. Includes table elements seen outside of a `<table>`.
+fn is_generic_block(tag: &str) -> bool {
+    matches!(tag, "div" | "article" | "section" | "table" | "tr" | "td" | "th")
 }
 
-struct MarkdownFormatter<'a> {
+/// Walks a parsed DOM and yields semantic `Event`s (`Start`/`End` of a `Container`, `Str` text,
+/// leaf `Atom`s) rather than building Markdown text directly. Element-specific emission (links,
+/// images, code blocks, tables) dispatches through `handler` so a `NodeHandler` implementation
+/// can override individual cases.
+///
+/// Events are collected eagerly into a `Vec` rather than streamed lazily: a true coroutine-style
+/// pull parser over a recursive DOM walk isn't expressible in stable Rust without unsafe, and the
+/// DOM itself is already fully materialized in memory by the time a `Parser` runs. The result is
+/// still a plain `Vec<Event>` a caller can `.map`/`.filter` before rendering -- see
+/// [`parse_events`] and [`Renderer`], the public entry points for doing so without forking this
+/// module. `Parser` itself stays `pub` only because [`crate::node_handler::NodeHandler`]'s
+/// methods take `&mut Parser`; embedders drive it through `ConvertConfig::node_handler` and
+/// `parse_events`/`html_to_markdown`, not by constructing one directly (its fields and DOM-walk
+/// entry points are crate-private).
+pub struct Parser {
     config: ConvertConfig,
-    content: String,
-    indent_level: usize,
-    list_type_stack: Vec<ListType>,
-    block_stack: Vec<BlockType>,
-    last_block_type: Option<BlockType>,
+    handler: Arc<dyn NodeHandler>,
+    events: Vec<Event>,
     in_table: bool,
-    table_columns: Vec<Cow<'a, str>>,
-    table_rows: Vec<Vec<Cow<'a, str>>>,
-    current_row: Vec<Cow<'a, str>>,
-    current_cell: String,
-    metadata: MetadataHandler,
     in_code_block: bool,
-    text_buffer: String,
-    link_buffer: String,
-    table_buffer: String,
-    last_was_block: bool,
-    preserve_next_whitespace: bool,
-    line_prefix: String,
     temp_buffer: String,
-    format_buffer: String,
-    node_buffer: String,
-    links: Vec<String>,
+    metadata: MetadataHandler,
 }
 
-impl<'a> MarkdownFormatter<'a> {
+impl Parser {
     fn new(config: ConvertConfig) -> Self {
+        let handler = config.node_handler.clone()
+            .unwrap_or_else(|| Arc::new(DefaultNodeHandler) as Arc<dyn NodeHandler>);
         Self {
             config,
-            content: String::with_capacity(16384),
-            indent_level: 0,
-            list_type_stack: Vec::with_capacity(8),
-            block_stack: Vec::with_capacity(16),
-            last_block_type: None,
+            handler,
+            events: Vec::with_capacity(512),
             in_table: false,
-            table_columns: Vec::with_capacity(8),
-            table_rows: Vec::with_capacity(20),
-            current_row: Vec::with_capacity(8),
-            current_cell: String::with_capacity(256),
-            metadata: MetadataHandler::new(),
             in_code_block: false,
-            text_buffer: String::with_capacity(2048),
-            link_buffer: String::with_capacity(256),
-            table_buffer: String::with_capacity(4096),
-            last_was_block: false,
-            preserve_next_whitespace: false,
-            line_prefix: String::with_capacity(32),
             temp_buffer: String::with_capacity(1024),
-            format_buffer: String::with_capacity(1024),
-            node_buffer: String::with_capacity(2048),
-            links: Vec::new(),
+            metadata: MetadataHandler::new(),
         }
     }
 
-    fn add_block_spacing(&mut self, block_type: BlockType) {
-        match block_type {
-            BlockType::Header(_) => {
-                if !self.content.ends_with("\n\n") {
-                    self.add_double_newline();
-                }
-            }
-            BlockType::Paragraph => {
-                if !self.last_was_block {
-                    self.add_double_newline();
-                }
-            }
-            BlockType::List(list_type) => {
-                if self.last_block_type != Some(BlockType::List(list_type)) {
-                    self.add_newline();
-                }
-            }
-            BlockType::CodeBlock | BlockType::Pre => {
-                self.add_double_newline();
-                self.preserve_next_whitespace = true;
-            }
-            BlockType::Quote => {
-                if !self.content.ends_with('\n') {
-                    self.add_newline();
-                }
-            }
-            _ => if !self.content.ends_with('\n') {
-                self.add_newline();
-            }
-        }
+    /// Appends `event` to the output; how a `NodeHandler` override emits its own rendering.
+    pub fn emit(&mut self, event: Event) {
+        self.events.push(event);
+    }
 
-        self.last_block_type = Some(block_type);
-        self.last_was_block = true;
+    fn finish(self) -> (Vec<Event>, MetadataHandler) {
+        (self.events, self.metadata)
     }
 
     fn should_skip_node(&self, handle: &Handle) -> bool {
@@ -170,64 +95,79 @@ impl<'a> MarkdownFormatter<'a> {
         }
     }
 
-    fn process_table_cell(&mut self, handle: &Handle) {
-        self.current_cell.clear();
-        self.current_cell.reserve(64);
-
-        self.process_children(handle);
+    /// Reads a `<th>`/`<td>`'s alignment off `style="text-align: ..."` (checked first, since it
+    /// wins in a browser) or the legacy `align="..."` attribute.
+    fn cell_alignment(attrs: &RefCell<Vec<html5ever::Attribute>>) -> Option<Alignment> {
+        let attrs = attrs.borrow();
 
-        let cell_content = self.current_cell.trim();
-        if cell_content.is_empty() {
-            self.current_row.push(Cow::Borrowed(""));
-        } else {
-            let cleaned = if self.config.clean_whitespace {
-                let needs_cleaning = cell_content.contains(|c: char| c.is_whitespace()) &&
-                                   !cell_content.chars().all(char::is_whitespace);
-
-                if needs_cleaning {
-                    self.node_buffer.clear();
-                    self.node_buffer.reserve(cell_content.len());
-
-                    let mut last_was_space = false;
-                    for c in cell_content.chars() {
-                        if c.is_whitespace() {
-                            if !last_was_space {
-                                self.node_buffer.push(' ');
-                                last_was_space = true;
-                            }
-                        } else {
-                            self.node_buffer.push(c);
-                            last_was_space = false;
-                        }
+        if let Some(style) = attrs.iter()
+            .find(|attr| attr.name.local.as_ref() == "style")
+            .map(|attr| attr.value.as_ref())
+        {
+            for declaration in style.split(';') {
+                let mut parts = declaration.splitn(2, ':');
+                let property = parts.next().unwrap_or("").trim();
+                let value = parts.next().unwrap_or("").trim();
+                if property.eq_ignore_ascii_case("text-align") {
+                    if let Some(alignment) = Self::parse_alignment(value) {
+                        return Some(alignment);
                     }
-                    Cow::Owned(self.node_buffer.clone())
-                } else {
-                    Cow::Owned(cell_content.to_string())
                 }
-            } else {
-                Cow::Owned(cell_content.to_string())
-            };
+            }
+        }
+
+        attrs.iter()
+            .find(|attr| attr.name.local.as_ref() == "align")
+            .and_then(|attr| Self::parse_alignment(attr.value.as_ref()))
+    }
 
-            self.current_row.push(cleaned);
+    fn parse_alignment(value: &str) -> Option<Alignment> {
+        match value.to_ascii_lowercase().as_str() {
+            "left" => Some(Alignment::Left),
+            "center" => Some(Alignment::Center),
+            "right" => Some(Alignment::Right),
+            _ => None,
         }
     }
 
-    fn clean_text<'b>(&mut self, text: &'b str) -> Cow<'b, str> {
-        if !self.config.clean_whitespace || self.in_code_block || self.preserve_next_whitespace {
-            self.preserve_next_whitespace = false;
-            return Cow::Borrowed(text);
+    /// Looks for a direct `<input type="checkbox">` child of an `<li>` and returns its checked
+    /// state, so the item is emitted as a GFM task-list entry rather than a plain bullet.
+    fn task_list_checkbox(li: &Handle) -> Option<bool> {
+        for child in li.children.borrow().iter() {
+            if let NodeData::Element { ref name, ref attrs, .. } = child.data {
+                if name.local.as_ref() != "input" {
+                    continue;
+                }
+                let attrs = attrs.borrow();
+                let is_checkbox = attrs.iter().any(|attr| {
+                    attr.name.local.as_ref() == "type" && attr.value.as_ref().eq_ignore_ascii_case("checkbox")
+                });
+                if is_checkbox {
+                    let checked = attrs.iter().any(|attr| attr.name.local.as_ref() == "checked");
+                    return Some(checked);
+                }
+            }
+        }
+        None
+    }
+
+    fn clean_text(&mut self, text: &str) -> String {
+        let text = crate::utils::decode_html_entities(text);
+
+        if !self.config.clean_whitespace || self.in_code_block {
+            return text.into_owned();
         }
 
         let trimmed = text.trim();
         if trimmed.is_empty() || trimmed.chars().all(char::is_whitespace) {
-            return Cow::Borrowed("");
+            return String::new();
         }
 
         let needs_cleaning = trimmed.contains(|c: char| c.is_whitespace()) &&
                            !trimmed.chars().all(char::is_whitespace);
 
         if !needs_cleaning {
-            return Cow::Borrowed(trimmed);
+            return trimmed.to_string();
         }
 
         self.temp_buffer.clear();
@@ -236,8 +176,7 @@ impl<'a> MarkdownFormatter<'a> {
         let mut last_was_space = false;
         let mut last_was_newline = false;
 
-        let mut chars = trimmed.chars().peekable();
-        while let Some(c) = chars.next() {
+        for c in trimmed.chars() {
             match c {
                 '\n' => {
                     if self.config.cleaning_rules.preserve_line_breaks && !last_was_newline {
@@ -264,7 +203,7 @@ impl<'a> MarkdownFormatter<'a> {
             }
         }
 
-        Cow::Owned(self.temp_buffer.clone())
+        self.temp_buffer.clone()
     }
 
     fn process_node(&mut self, handle: &Handle) {
@@ -281,85 +220,120 @@ impl<'a> MarkdownFormatter<'a> {
                         if self.config.preserve_headings {
                             let level = name[1..].parse::<u8>().unwrap();
                             if level <= self.config.max_heading_level {
-                                self.block_stack.push(BlockType::Header(level));
-                                self.add_block_spacing(BlockType::Header(level));
-                                self.process_header(handle, level);
-                                self.block_stack.pop();
+                                self.emit(Event::Start(Container::Heading(level)));
+                                self.process_children(handle);
+                                self.emit(Event::End(Container::Heading(level)));
                             }
                         }
                     }
 
                     "p" => {
-                        self.block_stack.push(BlockType::Paragraph);
-                        self.add_block_spacing(BlockType::Paragraph);
+                        if self.config.include_metadata && self.metadata.description.is_none() {
+                            self.maybe_set_description(handle);
+                        }
+                        self.emit(Event::Start(Container::Paragraph));
                         self.process_children(handle);
-                        self.block_stack.pop();
-                        self.add_newline();
+                        self.emit(Event::End(Container::Paragraph));
                     }
 
                     "pre" => {
-                        self.block_stack.push(BlockType::Pre);
-                        self.add_block_spacing(BlockType::Pre);
-                        self.process_code_block(handle, attrs);
-                        self.block_stack.pop();
+                        let handler = Arc::clone(&self.handler);
+                        handler.handle_code_block(handle, self);
                     }
 
                     "blockquote" => {
-                        self.block_stack.push(BlockType::Quote);
-                        self.add_block_spacing(BlockType::Quote);
-                        self.process_quote(handle);
-                        self.block_stack.pop();
+                        self.emit(Event::Start(Container::BlockQuote));
+                        self.process_children(handle);
+                        self.emit(Event::End(Container::BlockQuote));
                     }
 
-                    "a" => self.process_link(handle, attrs),
-                    "img" => self.process_image(handle, attrs),
-                    "meta" if self.config.include_metadata => self.extract_metadata(handle, attrs),
+                    "a" => {
+                        let handler = Arc::clone(&self.handler);
+                        handler.handle_link(handle, self);
+                    }
+                    "img" => {
+                        let handler = Arc::clone(&self.handler);
+                        handler.handle_image(handle, self);
+                    }
+                    "meta" if self.config.include_metadata => self.extract_metadata(attrs),
+                    "html" if self.config.include_metadata => {
+                        self.extract_language(attrs);
+                        self.process_children(handle);
+                    }
 
-                    "code" => self.process_inline_code(handle),
-                    "table" => self.process_table(handle),
+                    "code" => {
+                        self.emit(Event::Start(Container::InlineCode));
+                        let was_in_code = self.in_code_block;
+                        self.in_code_block = true;
+                        self.process_children(handle);
+                        self.in_code_block = was_in_code;
+                        self.emit(Event::End(Container::InlineCode));
+                    }
+                    "table" => {
+                        let handler = Arc::clone(&self.handler);
+                        handler.handle_table(handle, self);
+                    }
                     "tr" if self.in_table => {
-                        self.current_row.clear();
+                        self.emit(Event::Start(Container::TableRow));
                         self.process_children(handle);
-                        if !self.current_row.is_empty() {
-                            let mut new_row = Vec::with_capacity(self.current_row.len());
-                            new_row.extend_from_slice(&self.current_row);
-                            self.table_rows.push(new_row);
-                        }
-                    },
-                    "th" | "td" if self.in_table => self.process_table_cell(handle),
-
-                    "ul" => self.process_list(handle, ListType::Unordered),
-                    "ol" => self.process_list(handle, ListType::Ordered(1)),
-
-                    tag if INLINE_TAGS.contains_key(tag) => {
-                        let (prefix, suffix) = INLINE_TAGS[tag];
-                        self.content.push_str(prefix);
+                        self.emit(Event::End(Container::TableRow));
+                    }
+                    "th" | "td" if self.in_table => {
+                        let alignment = Self::cell_alignment(attrs);
+                        self.emit(Event::Start(Container::TableCell { alignment }));
                         self.process_children(handle);
-                        self.content.push_str(suffix);
+                        self.emit(Event::End(Container::TableCell { alignment }));
                     }
 
-                    tag if BLOCK_TAGS.contains_key(tag) => {
-                        self.add_double_newline();
+                    "dl" => {
+                        self.emit(Event::Start(Container::DefinitionList));
                         self.process_children(handle);
-                        self.add_double_newline();
+                        self.emit(Event::End(Container::DefinitionList));
+                    }
+                    "dt" => {
+                        self.emit(Event::Start(Container::DefinitionTerm));
+                        self.process_children(handle);
+                        self.emit(Event::End(Container::DefinitionTerm));
+                    }
+                    "dd" => {
+                        self.emit(Event::Start(Container::DefinitionDescription));
+                        self.process_children(handle);
+                        self.emit(Event::End(Container::DefinitionDescription));
+                    }
+
+                    "ul" => {
+                        self.emit(Event::Start(Container::List(ListKind::Unordered)));
+                        self.process_list(handle);
+                        self.emit(Event::End(Container::List(ListKind::Unordered)));
+                    }
+                    "ol" => {
+                        self.emit(Event::Start(Container::List(ListKind::Ordered(1))));
+                        self.process_list(handle);
+                        self.emit(Event::End(Container::List(ListKind::Ordered(1))));
                     }
 
-                    _ => self.process_children(handle),
+                    tag => {
+                        if let Some(container) = inline_container(tag) {
+                            self.emit(Event::Start(container.clone()));
+                            self.process_children(handle);
+                            self.emit(Event::End(container));
+                        } else if is_generic_block(tag) {
+                            self.emit(Event::Start(Container::Generic));
+                            self.process_children(handle);
+                            self.emit(Event::End(Container::Generic));
+                        } else {
+                            let handler = Arc::clone(&self.handler);
+                            handler.handle_element_fallback(handle, self);
+                        }
+                    }
                 }
             }
 
             NodeData::Text { contents } => {
                 let text = contents.borrow();
-                let text_content = if self.config.clean_whitespace && !self.in_code_block {
-                    self.clean_text(&text).into_owned()
-                } else {
-                    text.to_string()
-                };
-
-                if self.in_table {
-                    self.current_cell.push_str(&text_content);
-                } else {
-                    self.content.push_str(&text_content);
+                let cleaned = self.clean_text(&text);
+                if !cleaned.is_empty() {
+                    self.emit(Event::Str(cleaned));
                 }
             }
 
@@ -367,282 +341,144 @@ impl<'a> MarkdownFormatter<'a> {
         }
     }
 
-    fn process_quote(&mut self, handle: &Handle) {
-        let old_prefix = self.line_prefix.clone();
-        self.line_prefix.push_str("> ");
-
-        self.content.push_str(&self.line_prefix);
-        self.process_children(handle);
-
-        if !self.content.ends_with('\n') {
-            self.add_newline();
+    /// Recurses into `handle`'s children without emitting anything for `handle` itself. The
+    /// fallback a `NodeHandler::handle_*` override can call to keep descending the tree.
+    pub fn process_children(&mut self, handle: &Handle) {
+        for child in handle.children.borrow().iter() {
+            self.process_node(child);
         }
-
-        self.line_prefix = old_prefix;
     }
 
-    fn process_code_block(&mut self, handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
-        self.block_stack.push(BlockType::CodeBlock);
-        self.in_code_block = true;
-        self.add_double_newline();
-        self.content.push_str("```");
-
-        if let Some(class) = attrs.borrow().iter()
-            .find(|attr| attr.name.local.as_ref() == "class")
-            .map(|attr| attr.value.as_ref())
-        {
-            if let Some(lang) = class.split_whitespace()
-                .find(|c| c.starts_with("language-"))
-            {
-                self.content.push_str(&lang[9..]);
+    fn process_list(&mut self, handle: &Handle) {
+        for child in handle.children.borrow().iter() {
+            if let NodeData::Element { ref name, .. } = child.data {
+                if name.local.as_ref() == "li" {
+                    let checked = Self::task_list_checkbox(child);
+                    self.emit(Event::Start(Container::ListItem { checked }));
+                    self.process_node(child);
+                    self.emit(Event::End(Container::ListItem { checked }));
+                } else {
+                    self.process_node(child);
+                }
+            } else {
+                self.process_node(child);
             }
         }
-
-            self.content.push('\n');
-            self.process_children(handle);
-            self.content.push_str("\n```");
-            self.add_newline();
-            self.in_code_block = false;
-            self.block_stack.pop();
-        }
-
-    fn process_inline_code(&mut self, handle: &Handle) {
-        let was_in_code = self.in_code_block;
-        self.in_code_block = true;
-        self.content.push('`');
-        self.process_children(handle);
-        self.content.push('`');
-        self.in_code_block = was_in_code;
     }
 
-    fn process_header(&mut self, handle: &Handle, level: u8) {
-        self.add_double_newline();
-        self.content.push_str(&"#".repeat(level as usize));
-        self.content.push(' ');
-        self.process_children(handle);
-        self.add_double_newline();
-    }
+    /// Default `<a>` rendering: emits a `Link` container around the anchor's children. An
+    /// anchor with no `href` is dropped entirely, children included (matches the pre-existing
+    /// behavior this crate has always had for hrefless anchors).
+    pub fn default_link(&mut self, handle: &Handle) {
+        let attrs = match &handle.data {
+            NodeData::Element { attrs, .. } => attrs,
+            _ => return,
+        };
 
-    fn process_link(&mut self, handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
         if !self.config.include_links {
             self.process_children(handle);
             return;
         }
 
-        if let Some(ref href) = attrs.borrow().iter()
+        let href = attrs.borrow().iter()
             .find(|attr| attr.name.local.as_ref() == "href")
-            .map(|attr| attr.value.to_string())
-        {
-            self.link_buffer.clear();
-            let content_len = self.content.len();
-            self.process_children(handle);
-            self.link_buffer.clear();
-            self.link_buffer.push_str(&self.content[content_len..]);
-            self.content.truncate(content_len);
+            .map(|attr| attr.value.to_string());
 
-            if !self.link_buffer.is_empty() && self.link_buffer != *href {
-                self.content.push('[');
-                self.content.push_str(&self.link_buffer);
-                self.content.push_str("](");
-                self.content.push_str(href);
-                self.content.push(')');
-            } else {
-                self.content.push('<');
-                self.content.push_str(href);
-                self.content.push('>');
-            }
-
-            self.links.push(href.to_string());
-        }
-    }
-
-    fn process_table(&mut self, handle: &Handle) {
-        self.in_table = true;
-        self.table_columns.clear();
-        self.table_rows.clear();
-        self.table_buffer.clear();
-
-        self.process_children(handle);
-
-        if !self.table_rows.is_empty() {
-            let owned_rows: Vec<Vec<String>> = self.table_rows.iter()
-                .map(|row| row.iter().map(|cow| cow.to_string()).collect())
-                .collect();
-
-            let col_count = owned_rows.iter().map(|r| r.len()).max().unwrap_or(0);
-            let mut col_widths = vec![0; col_count];
-
-            for row in &owned_rows {
-                for (i, cell) in row.iter().enumerate() {
-                     if i < col_count {
-                         col_widths[i] = col_widths[i].max(cell.len());
-                     }
-                }
-            }
-
-            self.add_double_newline();
-
-            if let Some(header_row) = owned_rows.first() {
-                self.format_buffer.clear();
-                self.format_buffer.push('|');
-                for (i, cell) in header_row.iter().enumerate() {
-                    if i < col_widths.len() {
-                        let padding = col_widths[i].saturating_sub(cell.len());
-                        self.format_buffer.push(' ');
-                        self.format_buffer.push_str(cell);
-                        self.format_buffer.extend(std::iter::repeat(' ').take(padding));
-                        self.format_buffer.push_str(" |");
-                    }
-                }
-                // Pad remaining columns if header row is shorter
-                for i in header_row.len()..col_count {
-                     let padding = col_widths[i];
-                     self.format_buffer.push(' ');
-                     self.format_buffer.extend(std::iter::repeat(' ').take(padding));
-                     self.format_buffer.push_str(" |");
-                }
-                self.format_buffer.push('\n');
-                self.content.push_str(&self.format_buffer);
-
-                self.format_buffer.clear();
-                self.format_buffer.push('|');
-                for width in &col_widths {
-                    self.format_buffer.push_str(" ");
-                    self.format_buffer.push_str(&"-".repeat(*width));
-                    self.format_buffer.push_str(" |");
-                }
-                self.format_buffer.push('\n');
-                self.content.push_str(&self.format_buffer);
-            }
-
-            for row in owned_rows.iter().skip(1) {
-                self.format_buffer.clear();
-                self.format_buffer.push('|');
-                for (i, cell) in row.iter().enumerate() {
-                    if i < col_widths.len() {
-                        let padding = col_widths[i].saturating_sub(cell.len());
-                        self.format_buffer.push(' ');
-                        self.format_buffer.push_str(cell);
-                        self.format_buffer.extend(std::iter::repeat(' ').take(padding));
-                        self.format_buffer.push_str(" |");
-                    }
-                }
-                 // Pad remaining columns if row is shorter
-                for i in row.len()..col_count {
-                     let padding = col_widths[i];
-                     self.format_buffer.push(' ');
-                     self.format_buffer.extend(std::iter::repeat(' ').take(padding));
-                     self.format_buffer.push_str(" |");
-                }
-                self.format_buffer.push('\n');
-                self.content.push_str(&self.format_buffer);
-            }
-
-            self.add_newline();
+        if let Some(href) = href {
+            self.emit(Event::Start(Container::Link { href: href.clone() }));
+            self.process_children(handle);
+            self.emit(Event::End(Container::Link { href }));
         }
-
-        self.in_table = false;
     }
 
-
-    fn process_image(&mut self, _handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
+    /// Default `<img>` rendering: an `Image` atom carrying `src`/`alt`.
+    pub fn default_image(&mut self, handle: &Handle) {
+        let attrs = match &handle.data {
+            NodeData::Element { attrs, .. } => attrs,
+            _ => return,
+        };
         let attrs = attrs.borrow();
         let src = attrs.iter()
             .find(|attr| attr.name.local.as_ref() == "src")
-            .map(|attr| attr.value.as_ref());
-
+            .map(|attr| attr.value.to_string());
         let alt = attrs.iter()
             .find(|attr| attr.name.local.as_ref() == "alt")
-            .map(|attr| attr.value.as_ref())
+            .map(|attr| attr.value.to_string())
             .unwrap_or_default();
 
-        if let Some(url) = src {
-            self.add_newline();
-            self.content.push_str("![");
-            self.content.push_str(alt);
-            self.content.push_str("](");
-            self.content.push_str(url);
-            self.content.push(')');
-            self.add_newline();
+        if let Some(src) = src {
+            self.emit(Event::Atom(Atom::Image { src, alt }));
         }
     }
 
-    fn process_list(&mut self, handle: &Handle, list_type: ListType) {
-        self.block_stack.push(BlockType::List(list_type));
-        self.list_type_stack.push(list_type);
-        self.indent_level += match list_type {
-            ListType::Unordered => 2,
-            ListType::Ordered(_) => 3,
+    /// Default `<pre>` rendering: a `CodeBlock` container, with the language taken from a
+    /// `language-*` class on the element if present.
+    pub fn default_code_block(&mut self, handle: &Handle) {
+        let attrs = match &handle.data {
+            NodeData::Element { attrs, .. } => attrs,
+            _ => return,
         };
 
-        self.text_buffer.clear();
-        self.text_buffer.reserve(self.indent_level + 4);
+        let lang = attrs.borrow().iter()
+            .find(|attr| attr.name.local.as_ref() == "class")
+            .map(|attr| attr.value.to_string())
+            .and_then(|class| {
+                class.split_whitespace()
+                    .find(|c| c.starts_with("language-"))
+                    .map(|lang| lang[9..].to_string())
+            });
 
-        let mut current_count = match list_type {
-            ListType::Ordered(start) => start,
-            _ => 1, // Default start for unordered or if start is not specified
-        };
+        self.emit(Event::Start(Container::CodeBlock { lang: lang.clone() }));
+        self.in_code_block = true;
+        self.process_children(handle);
+        self.in_code_block = false;
+        self.emit(Event::End(Container::CodeBlock { lang }));
+    }
 
-        for child in handle.children.borrow().iter() {
-            if let NodeData::Element { ref name, .. } = child.data {
-                if name.local.as_ref() == "li" {
-                    self.text_buffer.clear();
-                    // Calculate indent based on current stack depth for nested lists
-                    let current_indent = self.list_type_stack.iter().fold(0, |acc, lt| {
-                        acc + match lt {
-                            ListType::Unordered => 2,
-                            ListType::Ordered(_) => 3,
-                        }
-                    }) - match list_type { // Subtract current level's base indent before adding prefix
-                        ListType::Unordered => 2,
-                        ListType::Ordered(_) => 3,
-                    };
+    /// Default `<table>` rendering: a `Table` container wrapping whatever `<tr>`/`<th>`/`<td>`
+    /// rows it contains.
+    pub fn default_table(&mut self, handle: &Handle) {
+        self.in_table = true;
+        self.emit(Event::Start(Container::Table));
+        self.process_children(handle);
+        self.emit(Event::End(Container::Table));
+        self.in_table = false;
+    }
 
-                    self.text_buffer.push_str(&" ".repeat(current_indent));
+    /// Falls back to the first `<p>`'s text for `metadata.description` when no explicit
+    /// `<meta name="description">`/`og:description` was found earlier in the document.
+    fn maybe_set_description(&mut self, handle: &Handle) {
+        let mut text = String::new();
+        extract_text(handle, &mut text);
+        let decoded = crate::utils::decode_html_entities(&text);
+        let trimmed = decoded.trim();
+        if trimmed.is_empty() {
+            return;
+        }
 
+        let max_len = self.config.auto_description_max_len.unwrap_or(200) as usize;
+        self.metadata.description = Some(Cow::Owned(truncate_description(trimmed, max_len)));
+    }
 
-                    match list_type {
-                        ListType::Unordered => {
-                            self.text_buffer.push_str("* ");
-                        },
-                        ListType::Ordered(_) => {
-                            // Corrected variable name from Â¤t_count to current_count
-                            self.text_buffer.push_str(&current_count.to_string());
-                            self.text_buffer.push_str(". ");
-                        },
-                    };
+    /// Reads the document language off `<html lang="...">`, e.g. `en` or `en-GB`.
+    fn extract_language(&mut self, attrs: &RefCell<Vec<html5ever::Attribute>>) {
+        if self.metadata.language.is_some() {
+            return;
+        }
 
-                    self.content.push_str(&self.text_buffer);
-                     // Add a newline before processing child if content doesn't end with newline
-                    // This helps separate list item content properly
-                    if !self.content.ends_with('\n') && !self.content.is_empty() {
-                        self.add_newline();
-                    }
-                    self.process_node(child);
-                    // Ensure a newline after processing the list item's content
-                    self.add_newline();
+        let lang = attrs.borrow().iter()
+            .find(|attr| attr.name.local.as_ref() == "lang")
+            .map(|attr| attr.value.to_string());
 
-                    current_count += 1;
-                } else {
-                    // Handle non-<li> elements inside <ul>/<ol> if necessary,
-                    // otherwise they might get processed without proper list context.
-                    // For now, just process them as children.
-                    self.process_node(child);
-                }
-            } else {
-                 // Handle text nodes or comments directly inside <ul>/<ol>
-                 self.process_node(child);
+        if let Some(lang) = lang {
+            let trimmed = lang.trim();
+            if !trimmed.is_empty() {
+                self.metadata.language = Some(Cow::Owned(trimmed.to_string()));
             }
         }
-
-        self.block_stack.pop();
-        self.list_type_stack.pop();
-        // Indent level is managed by stack, no need to subtract manually here
-        // self.indent_level -= match list_type { ... };
-        self.add_newline(); // Add a newline after the list finishes
     }
 
-    fn extract_metadata(&mut self, _handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
+    fn extract_metadata(&mut self, attrs: &RefCell<Vec<html5ever::Attribute>>) {
         let mut property_value = None;
         let mut name_value = None;
         let mut content_value = None;
@@ -653,7 +489,7 @@ impl<'a> MarkdownFormatter<'a> {
             match attr.name.local.as_ref() {
                 "property" => property_value = Some(attr.value.to_string()),
                 "name" => name_value = Some(attr.value.to_string()),
-                "content" => content_value = Some(attr.value.to_string()),
+                "content" => content_value = Some(crate::utils::decode_html_entities(attr.value.as_ref()).into_owned()),
                 _ => {}
             }
         }
@@ -686,12 +522,481 @@ impl<'a> MarkdownFormatter<'a> {
             }
         }
     }
+}
 
-    fn process_children(&mut self, handle: &Handle) {
-        for child in handle.children.borrow().iter() {
-            self.process_node(child);
+struct ListFrame {
+    kind: ListKind,
+    counter: u32,
+}
+
+impl ListFrame {
+    /// Column width a nested list's own marker takes up in its parent's indentation, mirroring
+    /// how deep Markdown indents sub-lists under an unordered (`* `) vs ordered (`1. `) bullet.
+    fn indent_width(kind: ListKind) -> usize {
+        match kind {
+            ListKind::Unordered => 2,
+            ListKind::Ordered(_) => 3,
         }
     }
+}
+
+/// Consumes a stream of `Event`s (typically straight from [`parse_events`], but just as happily
+/// from anything implementing `Iterator<Item = Event>` — including a caller's own `.map`/`.filter`
+/// middleware) and accumulates the final Markdown string.
+pub struct Renderer {
+    config: ConvertConfig,
+    content: String,
+    last_container: Option<Container>,
+    last_was_block: bool,
+    prefix_stack: Vec<String>,
+    line_prefix: String,
+    list_stack: Vec<ListFrame>,
+    table_rows: Vec<Vec<(String, Option<Alignment>)>>,
+    current_row: Vec<(String, Option<Alignment>)>,
+    in_cell: bool,
+    current_cell_buf: String,
+    current_cell_alignment: Option<Alignment>,
+    link_stack: Vec<(usize, String)>,
+    links: Vec<String>,
+    reference_links: Vec<String>,
+    heading_start: usize,
+    headings: Vec<(u8, String, String)>,
+    slug_counts: std::collections::HashMap<String, u32>,
+    metadata: MetadataHandler,
+}
+
+impl Renderer {
+    /// Builds a `Renderer` ready to consume events for `config`, seeded with whatever metadata
+    /// (title, description, ...) was already collected while parsing -- typically the second
+    /// element of [`parse_events`]'s return value.
+    pub fn new(config: ConvertConfig, metadata: MetadataHandler) -> Self {
+        Self {
+            config,
+            content: String::with_capacity(16384),
+            last_container: None,
+            last_was_block: false,
+            prefix_stack: Vec::new(),
+            line_prefix: String::new(),
+            list_stack: Vec::with_capacity(8),
+            table_rows: Vec::with_capacity(20),
+            current_row: Vec::with_capacity(8),
+            in_cell: false,
+            current_cell_buf: String::with_capacity(256),
+            current_cell_alignment: None,
+            link_stack: Vec::new(),
+            links: Vec::new(),
+            reference_links: Vec::new(),
+            heading_start: 0,
+            headings: Vec::new(),
+            slug_counts: std::collections::HashMap::new(),
+            metadata,
+        }
+    }
+
+    /// Returns the 1-based reference number for `href`, reusing an earlier link's number if the
+    /// same href was already referenced (matches how reference-style Markdown is normally
+    /// hand-written, with one definition per distinct URL).
+    fn reference_index(&mut self, href: &str) -> usize {
+        if let Some(index) = self.reference_links.iter().position(|existing| existing == href) {
+            return index + 1;
+        }
+        self.reference_links.push(href.to_string());
+        self.reference_links.len()
+    }
+
+    /// Applies every event in order and returns the finished `HtmlConversionResult`. `events` can
+    /// be [`parse_events`]'s output as-is, or a caller's own `.map`/`.filter` over it.
+    pub fn render(mut self, events: impl Iterator<Item = Event>) -> HtmlConversionResult {
+        for event in events {
+            self.apply(event);
+        }
+        self.finish()
+    }
+
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Start(container) => self.start(container),
+            Event::End(container) => self.end(container),
+            Event::Str(text) => self.push_text(&text),
+            Event::Atom(atom) => self.push_atom(atom),
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if self.in_cell {
+            self.current_cell_buf.push_str(text);
+        } else {
+            self.content.push_str(text);
+        }
+    }
+
+    fn push_atom(&mut self, atom: Atom) {
+        match atom {
+            Atom::Image { src, alt } => {
+                self.add_newline();
+                self.content.push_str("![");
+                self.content.push_str(&alt);
+                self.content.push_str("](");
+                self.content.push_str(&src);
+                self.content.push(')');
+                self.add_newline();
+            }
+        }
+    }
+
+    fn start(&mut self, container: Container) {
+        match container {
+            Container::Heading(level) => {
+                self.add_double_newline();
+                self.content.push_str(&"#".repeat(level as usize));
+                self.content.push(' ');
+                self.heading_start = self.content.len();
+                self.last_container = Some(Container::Heading(level));
+                self.last_was_block = true;
+            }
+            Container::Paragraph => {
+                if !self.last_was_block {
+                    self.add_double_newline();
+                }
+                self.last_container = Some(Container::Paragraph);
+                self.last_was_block = true;
+            }
+            Container::BlockQuote => {
+                if !self.content.ends_with('\n') {
+                    self.add_newline();
+                }
+                self.prefix_stack.push(self.line_prefix.clone());
+                self.line_prefix.push_str("> ");
+                self.content.push_str(&self.line_prefix);
+                self.last_container = Some(Container::BlockQuote);
+                self.last_was_block = true;
+            }
+            Container::CodeBlock { lang } => {
+                self.add_double_newline();
+                self.content.push_str("```");
+                if let Some(lang) = &lang {
+                    self.content.push_str(lang);
+                }
+                self.content.push('\n');
+                self.last_container = Some(Container::CodeBlock { lang });
+                self.last_was_block = true;
+            }
+            Container::List(kind) => {
+                if self.last_container != Some(Container::List(kind)) {
+                    self.add_newline();
+                }
+                self.list_stack.push(ListFrame {
+                    kind,
+                    counter: match kind {
+                        ListKind::Ordered(start) => start,
+                        ListKind::Unordered => 1,
+                    },
+                });
+                self.last_container = Some(Container::List(kind));
+                self.last_was_block = true;
+            }
+            Container::ListItem { checked } => {
+                let ancestor_depth = self.list_stack.len().saturating_sub(1);
+                let current_indent: usize = self.list_stack[..ancestor_depth]
+                    .iter()
+                    .map(|frame| ListFrame::indent_width(frame.kind))
+                    .sum();
+
+                self.content.push_str(&" ".repeat(current_indent));
+
+                if let Some(frame) = self.list_stack.last() {
+                    match frame.kind {
+                        ListKind::Unordered => match checked {
+                            Some(true) => self.content.push_str("* [x] "),
+                            Some(false) => self.content.push_str("* [ ] "),
+                            None => self.content.push_str("* "),
+                        },
+                        ListKind::Ordered(_) => {
+                            self.content.push_str(&frame.counter.to_string());
+                            self.content.push_str(". ");
+                        }
+                    }
+                }
+
+                if !self.content.ends_with('\n') && !self.content.is_empty() {
+                    self.add_newline();
+                }
+            }
+            Container::Table => {
+                self.table_rows.clear();
+            }
+            Container::TableRow => {
+                self.current_row.clear();
+            }
+            Container::TableCell { alignment } => {
+                self.in_cell = true;
+                self.current_cell_buf.clear();
+                self.current_cell_alignment = alignment;
+            }
+            Container::Emphasis => self.content.push('*'),
+            Container::Strong => self.content.push_str("**"),
+            Container::Strikethrough => self.content.push_str("~~"),
+            Container::Mark => self.content.push_str("=="),
+            Container::Underline => self.content.push_str("__"),
+            Container::InlineCode => self.content.push('`'),
+            Container::Link { href } => {
+                self.link_stack.push((self.content.len(), href));
+            }
+            Container::Generic => {
+                self.add_double_newline();
+            }
+            Container::DefinitionList => {
+                self.add_double_newline();
+            }
+            Container::DefinitionTerm => {
+                if !self.last_was_block {
+                    self.add_newline();
+                }
+            }
+            Container::DefinitionDescription => {
+                self.add_newline();
+                match self.config.definition_list_style {
+                    DefinitionListStyle::Term => self.content.push_str(": "),
+                    DefinitionListStyle::Bullet => self.content.push_str("  - "),
+                }
+            }
+        }
+    }
+
+    fn end(&mut self, container: Container) {
+        match container {
+            Container::Heading(level) => {
+                if self.config.table_of_contents {
+                    let text = self.content[self.heading_start..].trim().to_string();
+                    if !text.is_empty() {
+                        let slug = self.unique_slug(slugify(&text));
+                        self.content.push_str(" {#");
+                        self.content.push_str(&slug);
+                        self.content.push('}');
+                        self.headings.push((level, text, slug));
+                    }
+                }
+                self.add_double_newline();
+            }
+            Container::Paragraph => self.add_newline(),
+            Container::BlockQuote => {
+                if !self.content.ends_with('\n') {
+                    self.add_newline();
+                }
+                self.line_prefix = self.prefix_stack.pop().unwrap_or_default();
+            }
+            Container::CodeBlock { .. } => {
+                self.content.push_str("\n```");
+                self.add_newline();
+            }
+            Container::List(_) => {
+                self.list_stack.pop();
+                self.add_newline();
+            }
+            Container::ListItem { .. } => {
+                self.add_newline();
+                if let Some(frame) = self.list_stack.last_mut() {
+                    if let ListKind::Ordered(_) = frame.kind {
+                        frame.counter += 1;
+                    }
+                }
+            }
+            Container::Table => self.finish_table(),
+            Container::TableRow => {
+                if !self.current_row.is_empty() {
+                    self.table_rows.push(std::mem::take(&mut self.current_row));
+                }
+            }
+            Container::TableCell { .. } => {
+                self.in_cell = false;
+                let trimmed = self.current_cell_buf.trim();
+                let cell = if trimmed.is_empty() {
+                    String::new()
+                } else {
+                    self.clean_cell_text(trimmed)
+                };
+                self.current_row.push((cell, self.current_cell_alignment));
+            }
+            Container::Emphasis => self.content.push('*'),
+            Container::Strong => self.content.push_str("**"),
+            Container::Strikethrough => self.content.push_str("~~"),
+            Container::Mark => self.content.push_str("=="),
+            Container::Underline => self.content.push_str("__"),
+            Container::InlineCode => self.content.push('`'),
+            Container::Link { href } => self.finish_link(href),
+            Container::Generic => self.add_double_newline(),
+            Container::DefinitionList => self.add_double_newline(),
+            Container::DefinitionTerm => self.add_newline(),
+            Container::DefinitionDescription => self.add_newline(),
+        }
+    }
+
+    /// Disambiguates a slug against every heading slug produced so far, GitHub-style: the first
+    /// occurrence of a slug keeps it as-is, later occurrences get `-1`, `-2`, ... appended.
+    fn unique_slug(&mut self, base: String) -> String {
+        let count = self.slug_counts.entry(base.clone()).or_insert(0);
+        let suffix = *count;
+        *count += 1;
+        if suffix == 0 {
+            base
+        } else {
+            format!("{base}-{suffix}")
+        }
+    }
+
+    fn clean_cell_text(&self, text: &str) -> String {
+        if !self.config.clean_whitespace {
+            return text.to_string();
+        }
+        let needs_cleaning = text.contains(|c: char| c.is_whitespace()) &&
+                           !text.chars().all(char::is_whitespace);
+        if !needs_cleaning {
+            return text.to_string();
+        }
+
+        let mut cleaned = String::with_capacity(text.len());
+        let mut last_was_space = false;
+        for c in text.chars() {
+            if c.is_whitespace() {
+                if !last_was_space {
+                    cleaned.push(' ');
+                    last_was_space = true;
+                }
+            } else {
+                cleaned.push(c);
+                last_was_space = false;
+            }
+        }
+        cleaned
+    }
+
+    fn finish_link(&mut self, href: String) {
+        let Some((start_len, href)) = self.link_stack.pop() else {
+            return;
+        };
+
+        let inner = self.content[start_len..].to_string();
+        self.content.truncate(start_len);
+
+        if inner.is_empty() || inner == href {
+            self.content.push('<');
+            self.content.push_str(&href);
+            self.content.push('>');
+        } else {
+            match self.config.link_style {
+                LinkStyle::Inline => {
+                    self.content.push('[');
+                    self.content.push_str(&inner);
+                    self.content.push_str("](");
+                    self.content.push_str(&href);
+                    self.content.push(')');
+                }
+                LinkStyle::Reference => {
+                    let index = self.reference_index(&href);
+                    self.content.push('[');
+                    self.content.push_str(&inner);
+                    self.content.push_str("][");
+                    self.content.push_str(&index.to_string());
+                    self.content.push(']');
+                }
+            }
+        }
+
+        self.links.push(href);
+    }
+
+    /// Renders the buffered rows of the just-closed `Table` as a width-aligned GFM table, with a
+    /// delimiter row reflecting each column's alignment (the first cell in a column to specify
+    /// one wins).
+    fn finish_table(&mut self) {
+        let owned_rows = std::mem::take(&mut self.table_rows);
+        if owned_rows.is_empty() {
+            return;
+        }
+
+        let col_count = owned_rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        let mut col_widths = vec![0usize; col_count];
+        for row in &owned_rows {
+            for (i, (cell, _)) in row.iter().enumerate() {
+                if i < col_count {
+                    col_widths[i] = col_widths[i].max(cell.len());
+                }
+            }
+        }
+
+        let mut column_alignments: Vec<Option<Alignment>> = vec![None; col_count];
+        for row in &owned_rows {
+            for (i, (_, alignment)) in row.iter().enumerate() {
+                if i < col_count && column_alignments[i].is_none() {
+                    column_alignments[i] = *alignment;
+                }
+            }
+        }
+
+        self.add_double_newline();
+
+        if let Some(header_row) = owned_rows.first() {
+            self.write_table_row(header_row, &col_widths, col_count);
+
+            let mut delimiter = String::with_capacity(col_count * 6 + 1);
+            delimiter.push('|');
+            for (i, width) in col_widths.iter().enumerate() {
+                let alignment = column_alignments.get(i).copied().flatten();
+                let dashes = (*width).max(if alignment == Some(Alignment::Center) { 3 } else { 2 });
+                delimiter.push(' ');
+                match alignment {
+                    Some(Alignment::Left) => {
+                        delimiter.push(':');
+                        delimiter.extend(std::iter::repeat('-').take(dashes - 1));
+                    }
+                    Some(Alignment::Center) => {
+                        delimiter.push(':');
+                        delimiter.extend(std::iter::repeat('-').take(dashes - 2));
+                        delimiter.push(':');
+                    }
+                    Some(Alignment::Right) => {
+                        delimiter.extend(std::iter::repeat('-').take(dashes - 1));
+                        delimiter.push(':');
+                    }
+                    None => delimiter.extend(std::iter::repeat('-').take(dashes)),
+                }
+                delimiter.push_str(" |");
+            }
+            delimiter.push('\n');
+            self.content.push_str(&delimiter);
+        }
+
+        for row in owned_rows.iter().skip(1) {
+            self.write_table_row(row, &col_widths, col_count);
+        }
+
+        self.add_newline();
+    }
+
+    fn write_table_row(&mut self, row: &[(String, Option<Alignment>)], col_widths: &[usize], col_count: usize) {
+        let mut line = String::with_capacity(col_count * 8 + 1);
+        line.push('|');
+        for (i, (cell, _)) in row.iter().enumerate() {
+            if i < col_widths.len() {
+                let padding = col_widths[i].saturating_sub(cell.len());
+                line.push(' ');
+                line.push_str(cell);
+                line.extend(std::iter::repeat(' ').take(padding));
+                line.push_str(" |");
+            }
+        }
+        // Pad remaining columns if this row is shorter than the widest one.
+        for width in &col_widths[row.len().min(col_count)..col_count] {
+            line.push(' ');
+            line.extend(std::iter::repeat(' ').take(*width));
+            line.push_str(" |");
+        }
+        line.push('\n');
+        self.content.push_str(&line);
+    }
 
     fn add_newline(&mut self) {
         if !self.content.is_empty() && !self.content.ends_with('\n') {
@@ -700,97 +1005,248 @@ impl<'a> MarkdownFormatter<'a> {
     }
 
     fn add_double_newline(&mut self) {
-        // Ensure there are exactly two newlines, trimming excess first
         while self.content.ends_with('\n') {
             self.content.pop();
         }
         self.content.push_str("\n\n");
     }
 
+    /// Renders the headings collected during `render` as a nested Markdown list of
+    /// `[text](#slug)` links, indented by each heading's level relative to the shallowest one
+    /// seen (so a document starting at `<h2>` isn't indented one level for nothing).
+    fn render_table_of_contents(&self) -> String {
+        let min_level = self.headings.iter().map(|(level, _, _)| *level).min().unwrap_or(1);
+
+        let mut toc = String::from("## Table of Contents\n\n");
+        for (level, text, slug) in &self.headings {
+            let indent = (*level - min_level) as usize * 2;
+            toc.push_str(&" ".repeat(indent));
+            toc.push_str("- [");
+            toc.push_str(text);
+            toc.push_str("](#");
+            toc.push_str(slug);
+            toc.push_str(")\n");
+        }
+        toc.push('\n');
+        toc
+    }
+
+    fn finish(mut self) -> HtmlConversionResult {
+        let metadata = ConvertMetadata {
+            title: self.metadata.title.clone().map(Cow::into_owned),
+            author: self.metadata.author.clone().map(Cow::into_owned),
+            date: self.metadata.date.clone().map(Cow::into_owned),
+            description: self.metadata.description.clone().map(Cow::into_owned),
+            language: self.metadata.language.clone().map(Cow::into_owned),
+            tags: self.metadata.tags.iter().cloned().map(Cow::into_owned).collect(),
+        };
 
-    fn result(mut self) -> HtmlConversionResult {
         let mut final_content = String::with_capacity(
             self.content.len() +
-            if self.config.include_metadata { 1000 } else { 0 } // Estimate metadata size
+            if self.config.include_metadata { 1000 } else { 0 }
         );
 
         if self.config.include_metadata {
-             // Add title from <title> tag if OG title wasn't found
-             // This needs access to the DOM root, which isn't easily available here.
-             // Consider extracting title earlier or passing the DOM.
-             // For now, relies only on meta tags.
-            final_content.push_str(self.metadata.format_metadata());
+            final_content.push_str(self.metadata.format_metadata(self.config.front_matter));
+        }
+
+        if self.config.table_of_contents && !self.headings.is_empty() {
+            final_content.push_str(&self.render_table_of_contents());
         }
 
-        // Trim starting/ending whitespace from the main content before adding
         let trimmed_content = self.content.trim();
         final_content.push_str(trimmed_content);
 
+        if self.config.link_style == LinkStyle::Reference && !self.reference_links.is_empty() {
+            final_content.push_str("\n\n");
+            for (i, href) in self.reference_links.iter().enumerate() {
+                final_content.push('[');
+                final_content.push_str(&(i + 1).to_string());
+                final_content.push_str("]: ");
+                final_content.push_str(href);
+                final_content.push('\n');
+            }
+        }
 
         let markdown = if self.config.clean_whitespace && !self.config.cleaning_rules.preserve_line_breaks {
-            // Consolidate multiple blank lines into single blank lines (max two newlines)
             let mut cleaned = String::with_capacity(final_content.len());
             let mut newline_count = 0;
             for c in final_content.chars() {
-                 if c == '\n' {
-                     newline_count += 1;
-                 } else {
-                     newline_count = 0;
-                 }
-
-                 // Allow up to two consecutive newlines
-                 if newline_count <= 2 {
-                     cleaned.push(c);
-                 }
+                if c == '\n' {
+                    newline_count += 1;
+                } else {
+                    newline_count = 0;
+                }
+                if newline_count <= 2 {
+                    cleaned.push(c);
+                }
             }
-            cleaned.trim().to_string() // Trim final whitespace
+            cleaned.trim().to_string()
         } else {
-            final_content.trim().to_string() // Just trim final whitespace
+            final_content.trim().to_string()
         };
 
         HtmlConversionResult {
-                markdown,
-                links: self.links
+            markdown,
+            links: self.links,
+            metadata,
         }
     }
 }
 
-pub fn html_to_markdown(html: &str, config: ConvertConfig) -> HtmlConversionResult {
+/// Parses `html` and walks the resulting DOM into a flat `Vec<Event>` plus whatever metadata
+/// (title, description, ...) was collected along the way, without rendering anything yet. This is
+/// the public seam for embedders: `.map`/`.filter` the events before handing them to
+/// [`Renderer::render`] to transform the document without forking this module, e.g. to drop
+/// images or rewrite link hrefs.
+pub fn parse_events(html: &str, config: &ConvertConfig) -> (Vec<Event>, MetadataHandler) {
     let dom = parse_document(RcDom::default(), Default::default())
         .from_utf8()
         .read_from(&mut html.as_bytes())
         .expect("Failed to parse HTML"); // Use expect for clearer error on parsing failure
 
-    let mut formatter = MarkdownFormatter::new(config);
+    let mut parser = Parser::new(config.clone());
+
+    if parser.config.include_metadata {
+        let mut first_h1 = None;
+        let mut h1_count = 0u32;
+        find_title_tag(&dom.document, &mut parser.metadata, &mut first_h1, &mut h1_count);
+
+        // With no <title> at all, fall back to the page's first <h1>. When the <title> we found is
+        // implausible as an article title (too short to be more than a fragment, or too long to
+        // not be the whole nav/breadcrumb trail pasted in), replace it with the <h1> only when
+        // there's exactly one, since with several we can't tell which one is the article's own
+        // title.
+        let title_len = parser.metadata.title.as_deref().map(str::len);
+        let should_use_h1 = match title_len {
+            None => true,
+            Some(len) => h1_count == 1 && !(15..=150).contains(&len),
+        };
+        if should_use_h1 {
+            if let Some(h1) = first_h1 {
+                parser.metadata.title = Some(Cow::Owned(h1));
+            }
+        }
+    }
+
+    parser.process_node(&dom.document);
+    parser.finish()
+}
 
-    // Find title tag specifically if metadata.title is still None
-    if formatter.config.include_metadata && formatter.metadata.title.is_none() {
-        find_title_tag(&dom.document, &mut formatter.metadata);
+pub fn html_to_markdown(html: &str, config: ConvertConfig) -> HtmlConversionResult {
+    let (events, metadata) = parse_events(html, &config);
+    Renderer::new(config, metadata).render(events.into_iter())
+}
+
+/// Truncates `text` to at most `max_len` characters, backing off to the previous word boundary
+/// and appending `...` so an auto-derived description doesn't end mid-word.
+fn truncate_description(text: &str, max_len: usize) -> String {
+    if text.chars().count() <= max_len {
+        return text.to_string();
     }
 
+    let mut truncated: String = text.chars().take(max_len).collect();
+    if let Some(last_space) = truncated.rfind(' ') {
+        truncated.truncate(last_space);
+    }
+    truncated.push_str("...");
+    truncated
+}
+
+/// Turns heading text into a GitHub-style anchor slug: lowercased, non-word characters dropped,
+/// runs of whitespace/hyphens/underscores collapsed to a single `-`, leading/trailing `-` trimmed.
+fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    let mut last_was_dash = false;
+
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+            last_was_dash = false;
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !last_was_dash {
+            slug.push('-');
+            last_was_dash = true;
+        }
+    }
 
-    formatter.process_node(&dom.document);
-    formatter.result()
+    slug.trim_matches('-').to_string()
 }
 
-// Helper function to find the <title> tag content
-fn find_title_tag(handle: &Handle, metadata: &mut MetadataHandler) {
+/// Separators a site commonly tacks its name onto a `<title>` with (`Article Title | Site Name`,
+/// `Article Title - Site Name`, ...), checked in the order Readability's `getArticleTitle` tries
+/// them.
+const TITLE_SEPARATORS: [&str; 6] = [" | ", " - ", " — ", " – ", " :: ", " » "];
+
+/// Strips a trailing/leading site name off a `<title>`, mirroring Readability's heuristic: split
+/// on the *last* occurrence of a separator and keep whichever side has more words (the site name
+/// is usually the shorter side, and titles that themselves contain the separator -- "Part One |
+/// Part Two | Site Name" -- should keep everything but the trailing site name). If that split
+/// throws away too much, fall back to splitting on the separator's *first* occurrence instead.
+/// Either way, anything under three words is assumed to be the separator firing on the article
+/// title itself rather than an actual site-name suffix, so the title is left alone.
+fn clean_title(raw: &str) -> String {
+    let trimmed = raw.trim();
+
+    for separator in TITLE_SEPARATORS {
+        if let Some(candidate) = split_on_separator(trimmed, separator, trimmed.rfind(separator)) {
+            return candidate;
+        }
+        if let Some(candidate) = split_on_separator(trimmed, separator, trimmed.find(separator)) {
+            return candidate;
+        }
+    }
+
+    trimmed.to_string()
+}
+
+/// Splits `title` at `separator_pos` (a byte index into `title` where `separator` starts) into a
+/// left and right half, and returns whichever half has more words -- but only if it clears the
+/// three-word floor `clean_title` requires to treat it as a real title rather than a fragment.
+fn split_on_separator(title: &str, separator: &str, separator_pos: Option<usize>) -> Option<String> {
+    let pos = separator_pos?;
+    let left = &title[..pos];
+    let right = &title[pos + separator.len()..];
+
+    let longest = if right.split_whitespace().count() > left.split_whitespace().count() {
+        right
+    } else {
+        left
+    };
+
+    (longest.split_whitespace().count() >= 3).then(|| longest.trim().to_string())
+}
+
+/// Walks the whole document collecting the `<title>` tag's content (first one wins) alongside the
+/// first `<h1>`'s text and a count of how many `<h1>`s the page has in total -- the count is what
+/// lets `parse_events` decide whether replacing an implausible `<title>` with the `<h1>` is even
+/// safe (only when there's exactly one candidate).
+fn find_title_tag(handle: &Handle, metadata: &mut MetadataHandler, first_h1: &mut Option<String>, h1_count: &mut u32) {
     match &handle.data {
         NodeData::Element { name, .. } if name.local.as_ref() == "title" => {
-            // Extract text content from the title tag
             let mut title_content = String::new();
             extract_text(handle, &mut title_content);
             if !title_content.is_empty() && metadata.title.is_none() {
-                metadata.title = Some(Cow::Owned(title_content.trim().to_string()));
+                let decoded = crate::utils::decode_html_entities(&title_content);
+                metadata.title = Some(Cow::Owned(clean_title(&decoded)));
+            }
+        }
+        NodeData::Element { name, .. } if name.local.as_ref() == "h1" => {
+            let mut h1_content = String::new();
+            extract_text(handle, &mut h1_content);
+            let decoded = crate::utils::decode_html_entities(&h1_content);
+            let trimmed = decoded.trim();
+            if !trimmed.is_empty() {
+                *h1_count += 1;
+                if first_h1.is_none() {
+                    *first_h1 = Some(trimmed.to_string());
+                }
             }
-            return; // Stop searching once title is found
         }
         _ => {}
     }
 
     for child in handle.children.borrow().iter() {
-         if metadata.title.is_some() { break; } // Stop if already found in a child
-        find_title_tag(child, metadata);
+        find_title_tag(child, metadata, first_h1, h1_count);
     }
 }
 
@@ -807,4 +1263,4 @@ fn extract_text(handle: &Handle, buffer: &mut String) {
         }
         _ => {} // Ignore comments, processing instructions, etc.
     }
-}
\ No newline at end of file
+}
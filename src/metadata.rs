@@ -1,10 +1,13 @@
 use std::borrow::Cow;
 
+use crate::config::FrontMatterFormat;
+
 pub struct MetadataHandler {
     pub title: Option<Cow<'static, str>>,
     pub author: Option<Cow<'static, str>>,
     pub date: Option<Cow<'static, str>>,
     pub description: Option<Cow<'static, str>>,
+    pub language: Option<Cow<'static, str>>,
     pub tags: Vec<Cow<'static, str>>,
     metadata_buffer: String,
 }
@@ -16,14 +19,59 @@ impl MetadataHandler {
             author: None,
             date: None,
             description: None,
+            language: None,
             tags: Vec::with_capacity(10),
             metadata_buffer: String::with_capacity(1024),
         }
     }
 
-    pub fn format_metadata(&mut self) -> &str {
+    /// Renders the collected metadata per `format`: the ad-hoc `# Title` heading plus a
+    /// `---`-delimited `Key: value` header this crate has always defaulted to (`Plain`), or a YAML
+    /// (`---`/`---`) or TOML (`+++`/`+++`) front-matter block for callers who opt in. Only `Plain`
+    /// pulls `title` out into a leading `# ` heading; `Yaml`/`Toml` include it as a front-matter
+    /// field like everything else.
+    pub fn format_metadata(&mut self, format: FrontMatterFormat) -> &str {
         self.metadata_buffer.clear();
 
+        if format == FrontMatterFormat::Plain {
+            return self.format_plain();
+        }
+
+        let fence = match format {
+            FrontMatterFormat::Plain => unreachable!(),
+            FrontMatterFormat::Yaml => "---",
+            FrontMatterFormat::Toml => "+++",
+        };
+
+        self.metadata_buffer.push_str(fence);
+        self.metadata_buffer.push('\n');
+
+        if let Some(title) = self.title.clone() {
+            self.push_string_field(format, "title", &title);
+        }
+        if let Some(author) = self.author.clone() {
+            self.push_string_field(format, "author", &author);
+        }
+        if let Some(date) = self.date.clone() {
+            self.push_string_field(format, "date", &date);
+        }
+        if let Some(language) = self.language.clone() {
+            self.push_string_field(format, "language", &language);
+        }
+        if let Some(description) = self.description.clone() {
+            self.push_string_field(format, "description", &description);
+        }
+        if !self.tags.is_empty() {
+            let tags = self.tags.clone();
+            self.push_array_field(format, "tags", &tags);
+        }
+
+        self.metadata_buffer.push_str(fence);
+        self.metadata_buffer.push_str("\n\n");
+        &self.metadata_buffer
+    }
+
+    fn format_plain(&mut self) -> &str {
         if let Some(title) = &self.title {
             self.metadata_buffer.push_str("# ");
             self.metadata_buffer.push_str(title);
@@ -42,6 +90,11 @@ impl MetadataHandler {
             self.metadata_buffer.push_str(date);
             self.metadata_buffer.push('\n');
         }
+        if let Some(language) = &self.language {
+            self.metadata_buffer.push_str("Language: ");
+            self.metadata_buffer.push_str(language);
+            self.metadata_buffer.push('\n');
+        }
         if let Some(description) = &self.description {
             self.metadata_buffer.push_str("Description: ");
             self.metadata_buffer.push_str(description);
@@ -61,4 +114,52 @@ impl MetadataHandler {
         self.metadata_buffer.push_str("---\n\n");
         &self.metadata_buffer
     }
+
+    fn push_string_field(&mut self, format: FrontMatterFormat, key: &str, value: &str) {
+        let separator = match format {
+            FrontMatterFormat::Plain => unreachable!("Plain is rendered by format_plain"),
+            FrontMatterFormat::Yaml => ": ",
+            FrontMatterFormat::Toml => " = ",
+        };
+        self.metadata_buffer.push_str(key);
+        self.metadata_buffer.push_str(separator);
+        self.metadata_buffer.push_str(&quote(value));
+        self.metadata_buffer.push('\n');
+    }
+
+    fn push_array_field(&mut self, format: FrontMatterFormat, key: &str, values: &[Cow<'static, str>]) {
+        let separator = match format {
+            FrontMatterFormat::Plain => unreachable!("Plain is rendered by format_plain"),
+            FrontMatterFormat::Yaml => ": ",
+            FrontMatterFormat::Toml => " = ",
+        };
+        self.metadata_buffer.push_str(key);
+        self.metadata_buffer.push_str(separator);
+        self.metadata_buffer.push('[');
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                self.metadata_buffer.push_str(", ");
+            }
+            self.metadata_buffer.push_str(&quote(value));
+        }
+        self.metadata_buffer.push(']');
+        self.metadata_buffer.push('\n');
+    }
+}
+
+/// Wraps `value` in double quotes, escaping the characters that would otherwise terminate the
+/// quoted string in either YAML or TOML.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
 }
\ No newline at end of file
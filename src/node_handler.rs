@@ -0,0 +1,45 @@
+use markup5ever_rcdom::Handle;
+
+use crate::markdown::Parser;
+
+/// Extension point for the HTML-to-Markdown conversion, modeled on orgize's `HtmlHandler`: one
+/// method per element kind the parser would otherwise hard-code, each with a default impl that
+/// reproduces today's behavior. A caller that only wants to change how, say, images are rendered
+/// can implement `handle_image` and rely on the defaults for everything else.
+///
+/// `writer` is the in-progress `Parser`; handlers emit events through it directly (push text,
+/// recurse via `writer.process_children`, emit `Start`/`End`/`Atom` events, ...) rather than
+/// returning a value, since a single element can emit text, links, and nested block structure all
+/// at once.
+pub trait NodeHandler: std::fmt::Debug {
+    fn handle_link(&self, handle: &Handle, writer: &mut Parser) {
+        writer.default_link(handle);
+    }
+
+    fn handle_image(&self, handle: &Handle, writer: &mut Parser) {
+        writer.default_image(handle);
+    }
+
+    fn handle_code_block(&self, handle: &Handle, writer: &mut Parser) {
+        writer.default_code_block(handle);
+    }
+
+    fn handle_table(&self, handle: &Handle, writer: &mut Parser) {
+        writer.default_table(handle);
+    }
+
+    /// Called for any element that isn't otherwise special-cased (not a heading, `<p>`, `<pre>`,
+    /// `<blockquote>`, `<a>`, `<img>`, `<code>`, table element, list, definition-list element, or
+    /// one of the inline/generic-block tags). The default just recurses
+    /// into the element's children, discarding the tag itself.
+    fn handle_element_fallback(&self, handle: &Handle, writer: &mut Parser) {
+        writer.process_children(handle);
+    }
+}
+
+/// The handler used when `ConvertConfig::node_handler` is unset: every method falls through to
+/// `Parser`'s built-in event emission.
+#[derive(Debug, Default)]
+pub struct DefaultNodeHandler;
+
+impl NodeHandler for DefaultNodeHandler {}
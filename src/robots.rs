@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::lock::Mutex;
+use texting_robots::Robot;
+use url::Url;
+use worker::*;
+
+use crate::fetch::fetch_url_with_timeout;
+
+/// User-agent token whose group of robots.txt rules `Robot::allowed` evaluates URLs against. The
+/// robots.txt fetch itself goes out with the usual generated browser headers, not this token.
+const USER_AGENT: &str = "web2mdbot";
+
+/// Per-host robots.txt cache shared across all workers of one crawl. `None` means the fetch or
+/// parse failed and we're treating the host as unrestricted rather than refetching on every URL.
+pub type RobotsCache = Arc<Mutex<HashMap<String, Option<Arc<Robot>>>>>;
+
+pub fn new_cache() -> RobotsCache {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+pub struct RobotsDecision {
+    pub allowed: bool,
+    pub crawl_delay: Option<Duration>,
+}
+
+/// Looks up whether `url` may be crawled under its host's robots.txt, fetching and caching the
+/// robots.txt on first use. Hosts with no robots.txt, or one we failed to fetch/parse, are treated
+/// as unrestricted.
+pub async fn check(cache: &RobotsCache, url: &str) -> RobotsDecision {
+    let host = match Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_string)) {
+        Some(host) => host,
+        None => return RobotsDecision { allowed: true, crawl_delay: None },
+    };
+
+    match robot_for_host(cache, &host).await {
+        Some(robot) => RobotsDecision {
+            allowed: robot.allowed(url),
+            crawl_delay: robot.delay.map(Duration::from_secs_f32),
+        },
+        None => RobotsDecision { allowed: true, crawl_delay: None },
+    }
+}
+
+async fn robot_for_host(cache: &RobotsCache, host: &str) -> Option<Arc<Robot>> {
+    {
+        let cached = cache.lock().await;
+        if let Some(entry) = cached.get(host) {
+            return entry.clone();
+        }
+    }
+
+    let robots_url = format!("https://{}/robots.txt", host);
+    let robot = match fetch_url_with_timeout(&robots_url, 10000).await {
+        Ok(body) => match Robot::new(USER_AGENT, body.as_bytes()) {
+            Ok(robot) => Some(Arc::new(robot)),
+            Err(e) => {
+                console_warn!("Failed to parse robots.txt for {}: {}", host, e);
+                None
+            }
+        },
+        Err(e) => {
+            console_warn!("Failed to fetch robots.txt for {}: {}", host, e);
+            None
+        }
+    };
+
+    let mut cached = cache.lock().await;
+    cached.insert(host.to_string(), robot.clone());
+    robot
+}
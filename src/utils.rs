@@ -1,20 +1,202 @@
 // Removed unused Headers import
-use worker::{Response, Result};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use worker::{Env, Response, Result};
 
-pub fn add_cors_headers(mut resp: Response) -> Result<Response> {
+/// Computes a weak-ish ETag for `content`: good enough to detect byte-identical conversions across
+/// requests without pulling in a dedicated hashing crate for it.
+pub fn compute_etag(content: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Checks an `If-None-Match` header value (which may list several etags, or be `*`) against the
+/// etag of the response we're about to send.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if if_none_match.trim() == "*" {
+        return true;
+    }
+    if_none_match.split(',').any(|candidate| candidate.trim().trim_start_matches("W/") == etag)
+}
+
+/// The resolved CORS policy for a request: the origin allow-list plus whether
+/// `Access-Control-Allow-Credentials: true` should be emitted alongside it.
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allow_credentials: bool,
+}
+
+/// Resolves the CORS policy from binding vars: `ALLOWED_ORIGINS` is a comma-separated list of
+/// exact origins (e.g. `https://a.com,https://b.com`), or a literal `*` entry to allow any origin
+/// -- which is also what this returns when the var isn't set, so a deployment that never
+/// configures it keeps today's wide-open behavior. `CORS_ALLOW_CREDENTIALS` opts into emitting
+/// `Access-Control-Allow-Credentials: true`; unset (or anything other than `true`/`1`) leaves it
+/// off, since that header being present unconditionally would be a footgun for deployments that
+/// never meant to allow credentialed cross-origin requests.
+pub fn cors_config(env: &Env) -> CorsConfig {
+    let allowed_origins = env.var("ALLOWED_ORIGINS")
+        .ok()
+        .map(|var| var.to_string())
+        .filter(|value| !value.trim().is_empty())
+        .map(|value| value.split(',').map(|origin| origin.trim().to_string()).collect())
+        .unwrap_or_else(|| vec!["*".to_string()]);
+
+    let allow_credentials = env.var("CORS_ALLOW_CREDENTIALS")
+        .ok()
+        .map(|var| var.to_string())
+        .is_some_and(|value| value == "true" || value == "1");
+
+    CorsConfig { allowed_origins, allow_credentials }
+}
+
+/// Picks the `Access-Control-Allow-Origin` value for a request's `Origin` against the configured
+/// allow-list: `*` in the list allows (and echoes as) any origin; otherwise the request's own
+/// `Origin` is echoed back only if it's an exact match, and `None` is returned -- meaning no CORS
+/// header at all -- for anything else, so disallowed origins don't get a CORS-enabled response.
+fn cors_allow_origin(origin: Option<&str>, allowed_origins: &[String]) -> Option<String> {
+    if allowed_origins.iter().any(|allowed| allowed == "*") {
+        return Some("*".to_string());
+    }
+    let origin = origin?;
+    allowed_origins.iter().any(|allowed| allowed == origin).then(|| origin.to_string())
+}
+
+pub fn add_cors_headers(mut resp: Response, origin: Option<&str>, cors: &CorsConfig) -> Result<Response> {
     let headers = resp.headers_mut(); // headers_mut() returns &mut Headers
-    headers.set("Access-Control-Allow-Origin", "*")?;
+    if let Some(allow_origin) = cors_allow_origin(origin, &cors.allowed_origins) {
+        headers.set("Access-Control-Allow-Origin", &allow_origin)?;
+        headers.set("Vary", "Origin")?;
+        if cors.allow_credentials {
+            headers.set("Access-Control-Allow-Credentials", "true")?;
+        }
+    }
     headers.set("Access-Control-Allow-Methods", "GET, POST, OPTIONS")?;
     headers.set("Access-Control-Allow-Headers", "Content-Type")?;
     Ok(resp)
 }
 
-pub fn handle_options_request() -> Result<Response> {
+/// Handles a CORS preflight `OPTIONS` request. An origin that isn't covered by a non-wildcard
+/// allow-list gets a bare 403 rather than a 200 with no CORS headers, so a disallowed origin can't
+/// mistake the response for a same-origin-equivalent success.
+pub fn handle_options_request(origin: Option<&str>, cors: &CorsConfig) -> Result<Response> {
+    let allow_origin = cors_allow_origin(origin, &cors.allowed_origins);
+    if allow_origin.is_none() && !cors.allowed_origins.iter().any(|allowed| allowed == "*") {
+        return Response::error("Origin not allowed", 403);
+    }
+
     let mut resp = Response::ok("")?;
     let headers = resp.headers_mut();
-    headers.set("Access-Control-Allow-Origin", "*")?;
+    if let Some(allow_origin) = allow_origin {
+        headers.set("Access-Control-Allow-Origin", &allow_origin)?;
+        headers.set("Vary", "Origin")?;
+        if cors.allow_credentials {
+            headers.set("Access-Control-Allow-Credentials", "true")?;
+        }
+    }
     headers.set("Access-Control-Allow-Methods", "GET, POST, OPTIONS")?;
     headers.set("Access-Control-Allow-Headers", "Content-Type")?;
     headers.set("Access-Control-Max-Age", "86400")?; // Cache preflight response for 1 day
     Ok(resp)
+}
+
+/// Splits a combined `Set-Cookie` header value (as returned by `Headers::get`, which joins
+/// repeated headers with ", ") back into individual cookie strings. A naive `split(", ")` breaks
+/// because `Expires` attributes themselves contain commas (e.g. `Expires=Wed, 09 Jun 2027 ...`);
+/// this only splits at a comma that is followed by a new `name=value` pair.
+pub fn split_set_cookie_string(raw: &str) -> Vec<String> {
+    let chars: Vec<char> = raw.chars().collect();
+    let len = chars.len();
+    let mut cookies = Vec::new();
+    let mut pos = 0;
+    let mut start = 0;
+
+    let is_special = |c: char| c == '=' || c == ';' || c == ',';
+
+    while pos < len {
+        if chars[pos] == ',' {
+            let comma = pos;
+            let mut lookahead = pos + 1;
+            while lookahead < len && chars[lookahead].is_whitespace() {
+                lookahead += 1;
+            }
+            let next_start = lookahead;
+            while lookahead < len && !is_special(chars[lookahead]) {
+                lookahead += 1;
+            }
+            if lookahead < len && chars[lookahead] == '=' {
+                cookies.push(chars[start..comma].iter().collect::<String>().trim().to_string());
+                start = next_start;
+                pos = next_start;
+                continue;
+            }
+        }
+        pos += 1;
+    }
+    cookies.push(chars[start..len].iter().collect::<String>().trim().to_string());
+    cookies.into_iter().filter(|c| !c.is_empty()).collect()
+}
+
+/// Decodes HTML character references (`&amp;`, `&#39;`, `&#x27;`, ...) that can still end up
+/// embedded in metadata pulled from `<meta content>`/`<title>` attribute and text values. Returns
+/// a borrowed slice when there's nothing to decode, since most inputs don't contain any.
+pub fn decode_html_entities(input: &str) -> Cow<str> {
+    if !input.contains('&') {
+        return Cow::Borrowed(input);
+    }
+
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(amp) = rest.find('&') {
+        output.push_str(&rest[..amp]);
+        let tail = &rest[amp + 1..];
+
+        let semicolon = tail.find(';').filter(|&i| i <= 32);
+        if let Some(semi) = semicolon {
+            if let Some(decoded) = decode_entity(&tail[..semi]) {
+                output.push(decoded);
+                rest = &tail[semi + 1..];
+                continue;
+            }
+        }
+
+        output.push('&');
+        rest = tail;
+    }
+    output.push_str(rest);
+
+    Cow::Owned(output)
+}
+
+/// Looks up a single HTML character reference body (the part between `&` and `;`), covering the
+/// required XML entities plus the handful of named entities common in article prose.
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(hex) = entity.strip_prefix('#').and_then(|rest| rest.strip_prefix('x').or_else(|| rest.strip_prefix('X'))) {
+        return u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+    }
+    if let Some(dec) = entity.strip_prefix('#') {
+        return dec.parse::<u32>().ok().and_then(char::from_u32);
+    }
+
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{00A0}',
+        "copy" => '\u{00A9}',
+        "reg" => '\u{00AE}',
+        "trade" => '\u{2122}',
+        "mdash" => '\u{2014}',
+        "ndash" => '\u{2013}',
+        "hellip" => '\u{2026}',
+        "lsquo" => '\u{2018}',
+        "rsquo" => '\u{2019}',
+        "ldquo" => '\u{201C}',
+        "rdquo" => '\u{201D}',
+        _ => return None,
+    })
 }
\ No newline at end of file